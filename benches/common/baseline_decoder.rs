@@ -0,0 +1,116 @@
+//! The decoder as it was before the slice fast path, kept to measure against.
+//!
+//! Chunks are pulled a byte at a time through `slice::Iter`, the index holds `Option<Pixel>` and every pixel is pushed
+//! into a `Vec` channel by channel, runs included. Only well formed files are supported and the output keeps the file's
+//! own channel count.
+
+use std::slice::Iter;
+
+const QOI_OP_INDEX: u8 = 0b00;
+const QOI_OP_DIFF: u8 = 0b01;
+const QOI_OP_LUMA: u8 = 0b10;
+const QOI_OP_RGB: u8 = 0b11111110;
+const QOI_OP_RGBA: u8 = 0b11111111;
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+struct Pixel {
+    r: u8,
+    g: u8,
+    b: u8,
+    a: u8,
+}
+
+pub fn decode(bytes: &[u8]) -> Vec<u8> {
+    let width = u32::from_be_bytes(bytes[4..8].try_into().unwrap()) as usize;
+    let height = u32::from_be_bytes(bytes[8..12].try_into().unwrap()) as usize;
+    let alpha_included = bytes[12] == 4;
+    let total_pixels = width * height;
+
+    let mut decoded = Vec::with_capacity(total_pixels * if alpha_included {4} else {3});
+    let mut iter = bytes[14..].iter();
+    let mut state = DecoderState::new();
+
+    let mut pixels_seen = 0;
+    while pixels_seen < total_pixels {
+        let tag = iter.next().unwrap();
+        pixels_seen += state.write_chunk(&mut decoded, tag, &mut iter, alpha_included);
+    }
+    decoded
+}
+
+struct DecoderState {
+    prev_pixel: Pixel,
+    index: [Option<Pixel>; 64],
+}
+
+impl DecoderState {
+    fn new() -> DecoderState {
+        DecoderState {
+            prev_pixel: Pixel { r: 0, g: 0, b: 0, a: 255 },
+            index: [None; 64],
+        }
+    }
+
+    fn write_chunk(&mut self, bytes: &mut Vec<u8>, tag: &u8, iter: &mut Iter<u8>, alpha_included: bool) -> usize {
+        let mut pixels_written = 1;
+        let current_pixel = match *tag {
+            QOI_OP_RGB => {
+                let r = *iter.next().unwrap();
+                let g = *iter.next().unwrap();
+                let b = *iter.next().unwrap();
+                Pixel { r, g, b, a: self.prev_pixel.a }
+            }
+            QOI_OP_RGBA => {
+                let r = *iter.next().unwrap();
+                let g = *iter.next().unwrap();
+                let b = *iter.next().unwrap();
+                let a = *iter.next().unwrap();
+                Pixel { r, g, b, a }
+            }
+            _ => match tag >> 6 {
+                QOI_OP_INDEX => {self.index[*tag as usize].unwrap_or(Pixel { r: 0, g: 0, b: 0, a: 0 })}
+                QOI_OP_DIFF => {
+                    let mut pixel = self.prev_pixel;
+                    pixel.r = pixel.r.wrapping_add((tag >> 4) & 0b11).wrapping_sub(2);
+                    pixel.g = pixel.g.wrapping_add((tag >> 2) & 0b11).wrapping_sub(2);
+                    pixel.b = pixel.b.wrapping_add(tag & 0b11).wrapping_sub(2);
+                    pixel
+                }
+                QOI_OP_LUMA => {
+                    let byte2 = *iter.next().unwrap();
+                    let dg = (tag & 0b0011_1111).wrapping_sub(32);
+                    let dr = ((byte2 >> 4).wrapping_sub(8)).wrapping_add(dg);
+                    let db = ((byte2 & 0b1111).wrapping_sub(8)).wrapping_add(dg);
+                    let mut pixel = self.prev_pixel;
+                    pixel.r = pixel.r.wrapping_add(dr);
+                    pixel.g = pixel.g.wrapping_add(dg);
+                    pixel.b = pixel.b.wrapping_add(db);
+                    pixel
+                }
+                _ => {
+                    pixels_written = ((tag & 0b0011_1111) + 1) as usize;
+                    self.prev_pixel
+                }
+            },
+        };
+
+        for _ in 0..pixels_written {
+            bytes.push(current_pixel.r);
+            bytes.push(current_pixel.g);
+            bytes.push(current_pixel.b);
+            if alpha_included {
+                bytes.push(current_pixel.a);
+            }
+        }
+
+        self.index[calculate_index(&current_pixel)] = Some(current_pixel);
+        self.prev_pixel = current_pixel;
+
+        pixels_written
+    }
+}
+
+fn calculate_index(pixel: &Pixel) -> usize {
+    let index: u32 = (pixel.r as u32) * 3 + (pixel.g as u32) * 5 + (pixel.b as u32) * 7 + (pixel.a as u32) * 11;
+    (index % 64) as usize
+}
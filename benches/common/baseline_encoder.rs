@@ -0,0 +1,214 @@
+//! The encoder as it was before the single pass hot loop, kept to measure against.
+//!
+//! Ops are picked by `find_operation` and then built by separate functions that compute the differences again, the
+//! index holds `Option<Pixel>` and every byte is pushed one at a time. Only packed RGB and RGBA input is supported.
+
+use jaqoi::{Channels, Colorspace, ImgMetadata};
+
+const QOI_OP_INDEX: u8 = 0b00;
+const QOI_OP_DIFF: u8 = 0b01;
+const QOI_OP_LUMA: u8 = 0b10;
+const QOI_OP_RUN: u8 = 0b11;
+const QOI_OP_RGB: u8 = 0b11111110;
+const QOI_OP_RGBA: u8 = 0b11111111;
+
+const END_MARKER: [u8; 8] = [0, 0, 0, 0, 0, 0, 0, 1];
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+struct Pixel {
+    r: u8,
+    g: u8,
+    b: u8,
+    a: u8,
+}
+
+#[allow(clippy::enum_variant_names)]
+#[derive(Eq, PartialEq, Debug)]
+enum Operation {
+    QoiOpRgb,
+    QoiOpRgba,
+    QoiOpIndex,
+    QoiOpDiff,
+    QoiOpLuma,
+    QoiOpRun,
+}
+
+pub fn encode(pixels: &[u8], metadata: &ImgMetadata) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    add_header(&mut bytes, metadata);
+
+    let alpha_included = metadata.channels == Channels::RGBA;
+    let bytes_per_pixel = if alpha_included {4} else {3};
+
+    let mut state = EncoderState::new();
+    for values in pixels.chunks_exact(bytes_per_pixel) {
+        let a = if alpha_included {values[3]} else {255};
+        state.add_pixel(&mut bytes, Pixel { r: values[0], g: values[1], b: values[2], a });
+    }
+    state.flush_run(&mut bytes);
+
+    bytes.extend_from_slice(&END_MARKER);
+    bytes
+}
+
+fn add_header(bytes: &mut Vec<u8>, metadata: &ImgMetadata) {
+    bytes.extend_from_slice(b"qoif");
+    bytes.extend_from_slice(&metadata.width.to_be_bytes());
+    bytes.extend_from_slice(&metadata.height.to_be_bytes());
+
+    match metadata.channels {
+        Channels::RGB => {bytes.push(3)}
+        Channels::RGBA => {bytes.push(4)}
+    }
+
+    match metadata.colorspace {
+        Colorspace::SrgbLinearAlpha => {bytes.push(0)}
+        Colorspace::AllLinearAlpha => {bytes.push(1)}
+    }
+}
+
+struct EncoderState {
+    index: [Option<Pixel>; 64],
+    previous_pixel: Pixel,
+    run_count: u8,
+}
+
+impl EncoderState {
+    fn new() -> EncoderState {
+        let zero_pixel = Pixel { r: 0, g: 0, b: 0, a: 0 };
+
+        let mut index: [Option<Pixel>; 64] = [None; 64];
+        index[calculate_index(&zero_pixel)] = Some(zero_pixel);
+
+        EncoderState {
+            index,
+            previous_pixel: Pixel { r: 0, g: 0, b: 0, a: 255 },
+            run_count: 0,
+        }
+    }
+
+    fn add_pixel(&mut self, bytes: &mut Vec<u8>, pixel: Pixel) {
+        let operation = find_operation(&self.previous_pixel, &pixel, &self.index);
+
+        if operation != Operation::QoiOpRun {
+            self.flush_run(bytes);
+        }
+
+        match operation {
+            Operation::QoiOpRgb => {push_rgb(&pixel, bytes)}
+            Operation::QoiOpRgba => {push_rgba(&pixel, bytes)}
+            Operation::QoiOpIndex => {push_index(&pixel, bytes)}
+            Operation::QoiOpDiff => {bytes.push(create_diff(&pixel, &self.previous_pixel))}
+            Operation::QoiOpLuma => {bytes.extend_from_slice(&create_diff_luma(&pixel, &self.previous_pixel))}
+            Operation::QoiOpRun => {
+                self.run_count += 1;
+                if self.run_count >= 63 {
+                    push_run(bytes, 62);
+                    self.run_count -= 62;
+                }
+            }
+        }
+
+        if operation != Operation::QoiOpRun {
+            self.index[calculate_index(&pixel)] = Some(pixel);
+        }
+        self.previous_pixel = pixel;
+    }
+
+    fn flush_run(&mut self, bytes: &mut Vec<u8>) {
+        if self.run_count > 0 {
+            push_run(bytes, self.run_count);
+            self.run_count = 0;
+        }
+    }
+}
+
+fn tag_byte(tag: u8, lower_bits: u8) -> u8 {
+    assert!(tag < 4);
+    assert!(lower_bits < 64);
+
+    (tag << 6) + lower_bits
+}
+
+fn calculate_index(pixel: &Pixel) -> usize {
+    let index: u32 = (pixel.r as u32) * 3 + (pixel.g as u32) * 5 + (pixel.b as u32) * 7 + (pixel.a as u32) * 11;
+    (index % 64) as usize
+}
+
+fn create_diff(curr: &Pixel, prev: &Pixel) -> u8 {
+    let dr = u8::wrapping_add(u8::wrapping_sub(curr.r, prev.r), 2);
+    let dg = u8::wrapping_add(u8::wrapping_sub(curr.g, prev.g), 2);
+    let db = u8::wrapping_add(u8::wrapping_sub(curr.b, prev.b), 2);
+
+    assert!(dr < 4);
+    assert!(dg < 4);
+    assert!(db < 4);
+
+    tag_byte(QOI_OP_DIFF, (dr << 4) + (dg << 2) + db)
+}
+
+fn create_diff_luma(curr: &Pixel, prev: &Pixel) -> [u8; 2] {
+    let dr = u8::wrapping_sub(curr.r, prev.r);
+    let dg = u8::wrapping_sub(curr.g, prev.g);
+    let db = u8::wrapping_sub(curr.b, prev.b);
+
+    let dr_dg = u8::wrapping_add(u8::wrapping_sub(dr, dg), 8);
+    let db_dg = u8::wrapping_add(u8::wrapping_sub(db, dg), 8);
+    let dg = u8::wrapping_add(dg, 32);
+
+    assert!(dg < 64);
+    assert!(dr_dg < 16);
+    assert!(db_dg < 16);
+
+    [tag_byte(QOI_OP_LUMA, dg), (dr_dg << 4) + db_dg]
+}
+
+fn find_operation(prev_pixel: &Pixel, curr_pixel: &Pixel, index: &[Option<Pixel>]) -> Operation {
+    if prev_pixel.a != curr_pixel.a { return Operation::QoiOpRgba; };
+    if *prev_pixel == *curr_pixel {return Operation::QoiOpRun;}
+    if let Some(index_pixel) = index[calculate_index(curr_pixel)] {
+        if index_pixel == *curr_pixel {return Operation::QoiOpIndex;}
+    }
+
+    let dr = u8::wrapping_sub(curr_pixel.r, prev_pixel.r);
+    let dg = u8::wrapping_sub(curr_pixel.g, prev_pixel.g);
+    let db = u8::wrapping_sub(curr_pixel.b, prev_pixel.b);
+
+    if u8::wrapping_add(dr, 2) < 4 && u8::wrapping_add(dg, 2) < 4 && u8::wrapping_add(db, 2) < 4 {
+        return Operation::QoiOpDiff;
+    }
+
+    let dr_dg_8 = u8::wrapping_add(u8::wrapping_sub(dr, dg), 8);
+    let db_dg_8 = u8::wrapping_add(u8::wrapping_sub(db, dg), 8);
+    let dg_32 = u8::wrapping_add(dg, 32);
+
+    if dg_32 < 64 && dr_dg_8 < 16 && db_dg_8 < 16 {
+        return Operation::QoiOpLuma;
+    }
+
+    Operation::QoiOpRgb
+}
+
+fn push_run(bytes: &mut Vec<u8>, run_length: u8) {
+    assert!(run_length > 0 && run_length < 63);
+    bytes.push(tag_byte(QOI_OP_RUN, run_length - 1));
+}
+
+fn push_rgb(pixel: &Pixel, bytes: &mut Vec<u8>) {
+    bytes.push(QOI_OP_RGB);
+    bytes.push(pixel.r);
+    bytes.push(pixel.g);
+    bytes.push(pixel.b);
+}
+
+fn push_rgba(pixel: &Pixel, bytes: &mut Vec<u8>) {
+    bytes.push(QOI_OP_RGBA);
+    bytes.push(pixel.r);
+    bytes.push(pixel.g);
+    bytes.push(pixel.b);
+    bytes.push(pixel.a);
+}
+
+fn push_index(pixel: &Pixel, bytes: &mut Vec<u8>) {
+    bytes.push(tag_byte(QOI_OP_INDEX, calculate_index(pixel) as u8));
+}
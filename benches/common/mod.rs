@@ -0,0 +1,81 @@
+#![allow(dead_code)]
+
+pub mod baseline_decoder;
+pub mod baseline_encoder;
+//the same generator and helpers the tests use
+#[path = "../../tests/common/mod.rs"]
+mod tests_common;
+
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+use jaqoi::{Channels, ImgMetadata};
+
+pub use tests_common::{channels_per_pixel, Rng};
+
+pub const WIDTH: u32 = 1920;
+pub const HEIGHT: u32 = 1080;
+
+pub fn metadata(channels: Channels) -> ImgMetadata {
+    tests_common::metadata(WIDTH, HEIGHT, channels)
+}
+
+/// Smooth gradients with sensor-like noise, mostly QOI_OP_DIFF and QOI_OP_LUMA.
+pub fn photo(channels: &Channels) -> Vec<u8> {
+    let mut rng = Rng::new(1);
+    let mut pixels = Vec::new();
+    for y in 0..HEIGHT {
+        for x in 0..WIDTH {
+            let noise = rng.next_u8() % 5;
+            pixels.extend([(x / 8 + y / 16) as u8 + noise, (y / 5) as u8 + noise / 2, (x / 9) as u8 + noise]);
+            if *channels == Channels::RGBA {
+                pixels.push(255);
+            }
+        }
+    }
+    pixels
+}
+
+/// Flat panels, borders and text-like specks from a small palette, mostly QOI_OP_RUN and QOI_OP_INDEX.
+pub fn ui(channels: &Channels) -> Vec<u8> {
+    const PALETTE: [[u8; 4]; 6] = [[255, 255, 255, 255], [240, 240, 240, 255], [30, 30, 30, 255], [0, 120, 215, 255], [200, 200, 200, 255], [0, 0, 0, 128]];
+
+    let mut rng = Rng::new(2);
+    let mut pixels = Vec::new();
+    for y in 0..HEIGHT {
+        for x in 0..WIDTH {
+            let panel = (x / 300 + y / 200 * 3) as usize % 4;
+            let color = match (x % 300, y % 20) {
+                (0..=1, _) => PALETTE[4],
+                (40..=260, 5..=12) if rng.next_u8() < 90 => PALETTE[2 + rng.next_u8() as usize % 2],
+                _ if y % 200 > 190 => PALETTE[5],
+                _ => PALETTE[panel % 2],
+            };
+            pixels.extend(&color[..channels_per_pixel(channels)]);
+        }
+    }
+    pixels
+}
+
+/// Uniformly random pixels, the worst case of mostly QOI_OP_RGB and QOI_OP_RGBA.
+pub fn noise(channels: &Channels) -> Vec<u8> {
+    let mut rng = Rng::new(3);
+    let len = (WIDTH * HEIGHT) as usize * channels_per_pixel(channels);
+    (0..len).map(|_| rng.next_u8()).collect()
+}
+
+pub type Generator = fn(&Channels) -> Vec<u8>;
+
+pub const IMAGES: [(&str, Generator); 3] = [("photo", photo), ("ui", ui), ("noise", noise)];
+
+/// Runs `f` repeatedly for about half a second and returns the throughput of the fastest run in MB/s of `bytes`.
+pub fn throughput<T>(bytes: usize, mut f: impl FnMut() -> T) -> f64 {
+    let mut best = Duration::MAX;
+    let started = Instant::now();
+    while started.elapsed() < Duration::from_millis(500) {
+        let start = Instant::now();
+        black_box(f());
+        best = best.min(start.elapsed());
+    }
+    bytes as f64 / best.as_secs_f64() / 1_000_000.0
+}
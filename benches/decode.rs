@@ -0,0 +1,27 @@
+//! Compares decoding throughput against the baseline decoder. Run with `cargo bench --bench decode`.
+
+mod common;
+
+use common::baseline_decoder;
+use jaqoi::Channels;
+
+fn main() {
+    println!("{:<8} {:<5} {:>12} {:>12} {:>8}", "image", "chans", "base MB/s", "MB/s", "speedup");
+
+    for (name, generator) in common::IMAGES {
+        for channels in [Channels::RGB, Channels::RGBA] {
+            let pixels = generator(&channels);
+            let metadata = common::metadata(channels);
+            let encoded = jaqoi::try_encode(&pixels, &metadata).unwrap();
+
+            assert_eq!(jaqoi::try_decode(&encoded).unwrap().1, pixels, "{name} doesn't round trip");
+            assert_eq!(baseline_decoder::decode(&encoded), pixels, "{name} doesn't round trip through the baseline");
+
+            //throughput counts decoded bytes, as the reference implementation's benchmark does
+            let baseline = common::throughput(pixels.len(), || baseline_decoder::decode(&encoded));
+            let current = common::throughput(pixels.len(), || jaqoi::try_decode(&encoded));
+
+            println!("{:<8} {:<5} {:>12.1} {:>12.1} {:>7.2}x", name, common::channels_per_pixel(&metadata.channels), baseline, current, current / baseline);
+        }
+    }
+}
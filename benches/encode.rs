@@ -0,0 +1,26 @@
+//! Compares encoding throughput against the baseline encoder. Run with `cargo bench --bench encode`.
+
+mod common;
+
+use common::baseline_encoder;
+use jaqoi::Channels;
+
+fn main() {
+    println!("{:<8} {:<5} {:>12} {:>12} {:>8} {:>7}", "image", "chans", "base MB/s", "MB/s", "speedup", "ratio");
+
+    for (name, generator) in common::IMAGES {
+        for channels in [Channels::RGB, Channels::RGBA] {
+            let pixels = generator(&channels);
+            let metadata = common::metadata(channels);
+
+            let encoded = jaqoi::try_encode(&pixels, &metadata).unwrap();
+            assert_eq!(encoded, baseline_encoder::encode(&pixels, &metadata), "{name} output differs from the baseline");
+
+            let baseline = common::throughput(pixels.len(), || baseline_encoder::encode(&pixels, &metadata));
+            let current = common::throughput(pixels.len(), || jaqoi::try_encode(&pixels, &metadata));
+            let ratio = encoded.len() as f64 / pixels.len() as f64;
+
+            println!("{:<8} {:<5} {:>12.1} {:>12.1} {:>7.2}x {:>7.3}", name, common::channels_per_pixel(&metadata.channels), baseline, current, current / baseline, ratio);
+        }
+    }
+}
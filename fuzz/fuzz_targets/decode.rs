@@ -0,0 +1,38 @@
+#![no_main]
+
+use jaqoi::{DecodeLimits, DecodeOptions, Pixel, PixelLayout, SeekTable, Strictness};
+use libfuzzer_sys::fuzz_target;
+
+//keeps lenient decoding from filling gigabytes for a header that claims a huge image
+const LIMITS: DecodeLimits = DecodeLimits {
+    max_width: u32::MAX,
+    max_height: u32::MAX,
+    max_pixels: 1 << 20,
+    max_alloc_bytes: usize::MAX,
+};
+
+fuzz_target!(|data: &[u8]| {
+    let strict = DecodeOptions::new().with_limits(LIMITS).with_layout(PixelLayout::RGBA);
+    let lenient = strict.clone().with_strictness(Strictness::Lenient { fill: Pixel::new(255, 0, 255, 255) });
+
+    if let Ok((metadata, pixels)) = strict.decode(data) {
+        assert_eq!(pixels.len(), metadata.width as usize * metadata.height as usize * 4);
+    }
+    if let Ok((metadata, pixels, _)) = lenient.decode_with_problems(data) {
+        //lenient decoding only fails on the header, so every pixel gets written
+        assert_eq!(pixels.len(), metadata.width as usize * metadata.height as usize * 4);
+    }
+
+    //a table built from the file only exists for valid files, and must decode to the same pixels
+    if let (Ok(table), Ok((_, pixels))) = (SeekTable::build(data, 3), strict.decode(data)) {
+        assert_eq!(jaqoi::decode_parallel(data, &table, PixelLayout::RGBA, &LIMITS, 4).unwrap().1, pixels);
+    }
+    if let Ok(table) = SeekTable::from_trailer(data) {
+        let _ = jaqoi::decode_rows(data, &table, 1..4, PixelLayout::RGBA, &LIMITS);
+        let _ = jaqoi::decode_parallel(data, &table, PixelLayout::RGBA, &LIMITS, 4);
+    }
+
+    let mut out = vec![0; 1 << 12];
+    let _ = lenient.with_layout(PixelLayout::BGRA).with_premultiplied_alpha(true).decode_into(data, &mut out);
+    let _ = strict.with_layout(PixelLayout::LA8).decode_into(data, &mut out);
+});
@@ -0,0 +1,15 @@
+#![no_main]
+
+use jaqoi::{read_metadata, read_metadata_from};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    //both parsers must agree on every input
+    let from_slice = read_metadata(data);
+    let from_reader = read_metadata_from(&mut &data[..]);
+    match (from_slice, from_reader) {
+        (Ok(slice), Ok(reader)) => assert_eq!(slice, reader),
+        (Err(slice), Err(reader)) => assert_eq!(slice, reader),
+        (slice, reader) => panic!("parsers disagree: {:?} and {:?}", slice, reader),
+    }
+});
@@ -0,0 +1,34 @@
+#![no_main]
+
+use jaqoi::{Channels, Colorspace, ImgMetadata};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    //the first byte picks the channels and the second the width, the rest is pixels
+    let [config, width, pixels @ ..] = data else {
+        return;
+    };
+    let (channels, channels_per_pixel) = match config & 1 {
+        0 => (Channels::RGB, 3),
+        _ => (Channels::RGBA, 4),
+    };
+    let width = *width as usize + 1;
+    let height = pixels.len() / channels_per_pixel / width;
+    if height == 0 {
+        return;
+    }
+    let pixels = &pixels[..width * height * channels_per_pixel];
+
+    let metadata = ImgMetadata {
+        width: width as u32,
+        height: height as u32,
+        channels,
+        colorspace: Colorspace::SrgbLinearAlpha,
+    };
+    let encoded = jaqoi::try_encode(pixels, &metadata).expect("valid image should encode");
+    assert!(encoded.len() <= jaqoi::max_encoded_len(&metadata));
+
+    let (decoded_metadata, decoded) = jaqoi::try_decode(&encoded).expect("encoded image should decode");
+    assert_eq!(decoded_metadata, metadata);
+    assert_eq!(decoded, pixels);
+});
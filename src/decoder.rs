@@ -1,124 +1,661 @@
-use std::slice::Iter;
-use crate::{Channels, Colorspace, ImgMetadata, Operation, Pixel, QOI_OP_DIFF, QOI_OP_INDEX, QOI_OP_LUMA, QOI_OP_RGB, QOI_OP_RGBA, QOI_OP_RUN};
+#[cfg(feature = "alloc")]
+use alloc::{vec, vec::Vec};
+use core::fmt;
+use core::slice::Iter;
+#[cfg(feature = "std")]
+use std::io::ErrorKind;
+
+use crate::layout::{PixelFormat, PixelLayout};
+use crate::output::{Output, SliceOutput};
+use crate::seek;
+use crate::simd;
+use crate::{Channels, Colorspace, ImgMetadata, Operation, Pixel, END_MARKER, HEADER_SIZE, QOI_PIXELS_MAX, QOI_OP_DIFF, QOI_OP_INDEX, QOI_OP_LUMA, QOI_OP_RGB, QOI_OP_RGBA, QOI_OP_RUN};
+
+/// Reasons a byte stream could not be decoded as a QOI image.
+///
+/// Every variant carries the byte offset into the input at which the problem was detected.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub enum QoiError {
+    /// The file does not start with `qoif`.
+    InvalidMagic { offset: usize },
+    /// The channels byte is neither 3 nor 4.
+    InvalidChannels { offset: usize, value: u8 },
+    /// The colorspace byte is neither 0 nor 1.
+    InvalidColorspace { offset: usize, value: u8 },
+    /// Width or height is zero, or the decoded size does not fit in memory.
+    InvalidDimensions { offset: usize, width: u32, height: u32 },
+    /// The input ended in the middle of the header or a chunk.
+    Truncated { offset: usize },
+    /// The pixels were decoded but the 8 byte end marker does not follow them.
+    MissingEndMarker { offset: usize },
+    /// The chunks describe a different number of pixels than the header.
+    PixelCountMismatch { offset: usize, expected: usize, actual: usize },
+    /// Unexpected bytes were found after the last pixel.
+    TrailingBytes { offset: usize },
+    /// The header's dimensions exceed `limit`, the first of the [`DecodeLimits`] in use that they break.
+    LimitExceeded { offset: usize, width: u32, height: u32, limit: Limit },
+    /// The output buffer is smaller than the decoded image. The offset is where the pixel data starts.
+    OutputTooSmall { offset: usize, required: usize, available: usize },
+    /// A buffer that must hold exactly one row has a different length. The offset is where decoding would continue.
+    BufferSizeMismatch { offset: usize, expected: usize, actual: usize },
+    /// A [`crate::SeekTable`] is malformed or doesn't match the file it is used with.
+    InvalidSeekTable { offset: usize },
+    /// The underlying reader failed for a reason other than running out of data.
+    #[cfg(feature = "std")]
+    Io { offset: usize, kind: ErrorKind },
+}
 
-pub fn decode(bytes: &Vec<u8>) -> (ImgMetadata, Vec<u8>) {
+impl QoiError {
+    pub fn offset(&self) -> usize {
+        match *self {
+            QoiError::InvalidMagic { offset }
+            | QoiError::InvalidChannels { offset, .. }
+            | QoiError::InvalidColorspace { offset, .. }
+            | QoiError::InvalidDimensions { offset, .. }
+            | QoiError::LimitExceeded { offset, .. }
+            | QoiError::Truncated { offset }
+            | QoiError::MissingEndMarker { offset }
+            | QoiError::PixelCountMismatch { offset, .. }
+            | QoiError::TrailingBytes { offset }
+            | QoiError::OutputTooSmall { offset, .. }
+            | QoiError::BufferSizeMismatch { offset, .. }
+            | QoiError::InvalidSeekTable { offset } => offset,
+            #[cfg(feature = "std")]
+            QoiError::Io { offset, .. } => offset,
+        }
+    }
+}
 
-    verify_ending(&bytes);
+impl fmt::Display for QoiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            QoiError::InvalidMagic { offset } => write!(f, "invalid magic bytes at offset {offset}, expected \"qoif\""),
+            QoiError::InvalidChannels { offset, value } => write!(f, "invalid channels value {value} at offset {offset}, expected 3 or 4"),
+            QoiError::InvalidColorspace { offset, value } => write!(f, "invalid colorspace value {value} at offset {offset}, expected 0 or 1"),
+            QoiError::InvalidDimensions { offset, width, height } => write!(f, "invalid dimensions {width}x{height} at offset {offset}"),
+            QoiError::LimitExceeded { offset, width, height, limit } => write!(f, "dimensions {width}x{height} at offset {offset} exceed {limit}"),
+            QoiError::Truncated { offset } => write!(f, "data truncated at offset {offset}"),
+            QoiError::MissingEndMarker { offset } => write!(f, "missing end marker at offset {offset}"),
+            QoiError::PixelCountMismatch { offset, expected, actual } => write!(f, "expected {expected} pixels but chunks describe {actual} at offset {offset}"),
+            QoiError::TrailingBytes { offset } => write!(f, "unexpected trailing bytes at offset {offset}"),
+            QoiError::OutputTooSmall { required, available, .. } => write!(f, "output buffer holds {available} bytes but the decoded image needs {required}"),
+            QoiError::BufferSizeMismatch { expected, actual, .. } => write!(f, "row buffer holds {actual} bytes but a row is {expected}"),
+            QoiError::InvalidSeekTable { offset } => write!(f, "seek table doesn't match the chunks at offset {offset}"),
+            #[cfg(feature = "std")]
+            QoiError::Io { offset, kind } => write!(f, "read error at offset {offset}: {kind}"),
+        }
+    }
+}
 
-    let mut iter = bytes[0..bytes.len()-8].iter();
-    let metadata = parse_metadata(&mut iter);
+#[cfg(feature = "std")]
+impl std::error::Error for QoiError {}
+
+/// Upper bounds on the images a decoder accepts, checked against the header before anything is allocated.
+///
+/// The default only enforces the [`QOI_PIXELS_MAX`] ceiling recommended by the specification.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct DecodeLimits {
+    pub max_width: u32,
+    pub max_height: u32,
+    pub max_pixels: u64,
+    /// Largest decoded image in bytes, which depends on the output layout.
+    pub max_alloc_bytes: usize,
+}
 
-    let channels_per_pixel = match metadata.channels {
-        Channels::RGB => {3}
-        Channels::RGBA => {4}
-    };
-    let total_pixels: usize = (metadata.width * metadata.height) as usize;
-    let mut decoded: Vec<u8> = Vec::with_capacity(total_pixels * channels_per_pixel);
+impl Default for DecodeLimits {
+    fn default() -> DecodeLimits {
+        DecodeLimits {
+            max_width: u32::MAX,
+            max_height: u32::MAX,
+            max_pixels: QOI_PIXELS_MAX,
+            max_alloc_bytes: usize::MAX,
+        }
+    }
+}
 
-    let include_alpha = match metadata.channels {
-        Channels::RGB => {false}
-        Channels::RGBA => {true}
-    };
-    if total_pixels > 0 {
+impl DecodeLimits {
+    /// Checks the image described by `metadata`, decoded into `layout`, against every limit.
+    pub(crate) fn check(&self, metadata: &ImgMetadata, layout: &PixelLayout) -> Result<(), QoiError> {
+        self.check_bytes_per_pixel(metadata, layout.bytes_per_pixel())
+    }
+
+    /// Checks the image described by `metadata` against every limit, allocating `bytes_per_pixel` bytes per pixel.
+    pub(crate) fn check_bytes_per_pixel(&self, metadata: &ImgMetadata, bytes_per_pixel: usize) -> Result<(), QoiError> {
+        let width = metadata.width;
+        let height = metadata.height;
+        let total_pixels = width as u64 * height as u64;
+        let total_bytes = (total_pixels as usize).checked_mul(bytes_per_pixel);
+
+        //the offset points at the header field that broke the limit
+        let (offset, limit) = if width > self.max_width {
+            (4, Limit::MaxWidth(self.max_width))
+        } else if height > self.max_height {
+            (8, Limit::MaxHeight(self.max_height))
+        } else if total_pixels > self.max_pixels {
+            (4, Limit::MaxPixels(self.max_pixels))
+        } else if total_bytes.map_or(true, |total_bytes| total_bytes > self.max_alloc_bytes) {
+            (4, Limit::MaxAllocBytes(self.max_alloc_bytes))
+        } else {
+            return Ok(());
+        };
+        Err(QoiError::LimitExceeded { offset, width, height, limit })
+    }
+}
+
+/// One of the [`DecodeLimits`] together with the value it was set to.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum Limit {
+    MaxWidth(u32),
+    MaxHeight(u32),
+    MaxPixels(u64),
+    MaxAllocBytes(usize),
+}
+
+impl fmt::Display for Limit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Limit::MaxWidth(value) => write!(f, "max_width of {value}"),
+            Limit::MaxHeight(value) => write!(f, "max_height of {value}"),
+            Limit::MaxPixels(value) => write!(f, "max_pixels of {value}"),
+            Limit::MaxAllocBytes(value) => write!(f, "max_alloc_bytes of {value}"),
+        }
+    }
+}
+
+/// How the decoder reacts to malformed pixel data. Header errors always fail.
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Default)]
+pub enum Strictness {
+    /// Fails on the first problem: a truncated chunk, a run past the last pixel, an early or missing end marker, or
+    /// bytes around the end marker.
+    #[default]
+    Strict,
+    /// Decodes as many pixels as possible and writes `fill` for the rest. Runs past the last pixel are cut short.
+    Lenient { fill: Pixel },
+}
+
+/// Settings for decoding a whole image at once.
+///
+/// By default pixels are written in the file's own channel count with straight alpha, [`DecodeLimits::default`]
+/// applies and decoding is [`Strictness::Strict`].
+#[derive(Clone, Debug, Default)]
+pub struct DecodeOptions {
+    layout: Option<PixelLayout>,
+    premultiplied: bool,
+    pub(crate) limits: DecodeLimits,
+    strictness: Strictness,
+}
+
+impl DecodeOptions {
+    pub fn new() -> DecodeOptions {
+        DecodeOptions::default()
+    }
+
+    /// Writes pixels in `layout` instead of the file's own channel count.
+    pub fn with_layout(mut self, layout: PixelLayout) -> DecodeOptions {
+        self.layout = Some(layout);
+        self
+    }
+
+    /// Multiplies the color channels by alpha as described in [`decode_into_premultiplied`].
+    pub fn with_premultiplied_alpha(mut self, premultiplied: bool) -> DecodeOptions {
+        self.premultiplied = premultiplied;
+        self
+    }
+
+    pub fn with_limits(mut self, limits: DecodeLimits) -> DecodeOptions {
+        self.limits = limits;
+        self
+    }
+
+    pub fn with_strictness(mut self, strictness: Strictness) -> DecodeOptions {
+        self.strictness = strictness;
+        self
+    }
+
+    /// Decodes a QOI file into its metadata and pixels.
+    ///
+    /// Problems skipped in [`Strictness::Lenient`] mode are not reported, see [`DecodeOptions::decode_with_problems`].
+    #[cfg(feature = "alloc")]
+    pub fn decode(&self, bytes: &[u8]) -> Result<(ImgMetadata, Vec<u8>), QoiError> {
+        self.decode_reporting(bytes, |_| {})
+    }
+
+    /// Decodes like [`DecodeOptions::decode`] and also returns the problems skipped in [`Strictness::Lenient`] mode,
+    /// in the order they were found. The list is always empty in [`Strictness::Strict`] mode.
+    #[cfg(feature = "alloc")]
+    pub fn decode_with_problems(&self, bytes: &[u8]) -> Result<(ImgMetadata, Vec<u8>, Vec<QoiError>), QoiError> {
+        let mut problems = Vec::new();
+        let (metadata, decoded) = self.decode_reporting(bytes, |err| problems.push(err))?;
+        Ok((metadata, decoded, problems))
+    }
+
+    /// Decodes into `out` without allocating, returning the metadata and the number of bytes written.
+    ///
+    /// Problems skipped in [`Strictness::Lenient`] mode are not reported.
+    pub fn decode_into(&self, bytes: &[u8], out: &mut [u8]) -> Result<(ImgMetadata, usize), QoiError> {
+        let metadata = read_metadata(bytes)?;
+        let format = self.format(&metadata);
+        self.limits.check(&metadata, &format.layout)?;
+
+        let required = decoded_len(&metadata, &format.layout);
+        if out.len() < required {
+            return Err(QoiError::OutputTooSmall { offset: HEADER_SIZE, required, available: out.len() });
+        }
+        self.decode_chunks(bytes, &format, &mut out[..required], |_| {})?;
+
+        Ok((metadata, required))
+    }
+
+    #[cfg(feature = "alloc")]
+    fn decode_reporting(&self, bytes: &[u8], report: impl FnMut(QoiError)) -> Result<(ImgMetadata, Vec<u8>), QoiError> {
+        let metadata = read_metadata(bytes)?;
+        let format = self.format(&metadata);
+        self.limits.check(&metadata, &format.layout)?;
+
+        let mut decoded: Vec<u8> = vec![0; decoded_len(&metadata, &format.layout)];
+        self.decode_chunks(bytes, &format, &mut decoded, report)?;
+
+        Ok((metadata, decoded))
+    }
+
+    /// Decodes the chunks, passing problems to `report` instead of failing in [`Strictness::Lenient`] mode.
+    fn decode_chunks(&self, bytes: &[u8], format: &PixelFormat, out: &mut [u8], mut report: impl FnMut(QoiError)) -> Result<(), QoiError> {
+        match self.strictness {
+            Strictness::Strict => {decode_chunks(bytes, format, &Pixel::rgb(0, 0, 0), out, &mut Err)}
+            Strictness::Lenient { fill } => {
+                decode_chunks(bytes, format, &fill, out, &mut |err| {
+                    report(err);
+                    Ok(())
+                })
+            }
+        }
+    }
+
+    fn format(&self, metadata: &ImgMetadata) -> PixelFormat {
+        PixelFormat {
+            layout: self.layout.unwrap_or(PixelLayout::from(&metadata.channels)),
+            premultiplied: self.premultiplied,
+        }
+    }
+}
+
+#[cfg(feature = "alloc")]
+pub fn decode(bytes: &[u8]) -> Result<(ImgMetadata, Vec<u8>), QoiError> {
+    DecodeOptions::new().decode(bytes)
+}
+
+#[cfg(feature = "alloc")]
+pub fn decode_with_layout(bytes: &[u8], layout: PixelLayout) -> Result<(ImgMetadata, Vec<u8>), QoiError> {
+    DecodeOptions::new().with_layout(layout).decode(bytes)
+}
+
+/// Decodes with the color channels premultiplied by alpha as described in [`decode_into_premultiplied`].
+#[cfg(feature = "alloc")]
+pub fn decode_premultiplied(bytes: &[u8], layout: PixelLayout) -> Result<(ImgMetadata, Vec<u8>), QoiError> {
+    DecodeOptions::new().with_layout(layout).with_premultiplied_alpha(true).decode(bytes)
+}
 
-        let num_pixels_parsed = parse_chunks(&mut iter, &mut decoded, include_alpha);
-        assert_eq!(total_pixels, num_pixels_parsed);
+/// Decodes into `out` without allocating, returning the metadata and the number of bytes written.
+pub fn decode_to_slice(bytes: &[u8], out: &mut [u8]) -> Result<(ImgMetadata, usize), QoiError> {
+    DecodeOptions::new().decode_into(bytes, out)
+}
 
+/// Decodes into `out` with `out_channels` bytes per pixel, whatever the file's own channel count.
+///
+/// Alpha is set to 255 when an RGB file is decoded as RGBA, and dropped when an RGBA file is decoded as RGB.
+/// Returns the metadata from the header and the number of bytes written.
+pub fn decode_into(bytes: &[u8], out: &mut [u8], out_channels: Channels) -> Result<(ImgMetadata, usize), QoiError> {
+    decode_into_with_layout(bytes, out, PixelLayout::from(&out_channels))
+}
+
+/// Decodes into `out` with the channels ordered as in `layout`, returning the metadata and the number of bytes written.
+pub fn decode_into_with_layout(bytes: &[u8], out: &mut [u8], layout: PixelLayout) -> Result<(ImgMetadata, usize), QoiError> {
+    DecodeOptions::new().with_layout(layout).decode_into(bytes, out)
+}
+
+/// Decodes into `out` with the color channels multiplied by alpha as each pixel is written.
+///
+/// Each channel becomes `(channel * alpha + 127) / 255`, which rounds to the nearest value. Layouts without alpha are
+/// premultiplied too, which is the same as compositing over black.
+pub fn decode_into_premultiplied(bytes: &[u8], out: &mut [u8], layout: PixelLayout) -> Result<(ImgMetadata, usize), QoiError> {
+    DecodeOptions::new().with_layout(layout).with_premultiplied_alpha(true).decode_into(bytes, out)
+}
+
+/// Number of bytes the pixels described by `metadata` take up once decoded into `layout`.
+pub(crate) fn decoded_len(metadata: &ImgMetadata, layout: &PixelLayout) -> usize {
+    metadata.width as usize * metadata.height as usize * layout.bytes_per_pixel()
+}
+
+/// Decodes the chunks following the header of `bytes` into `out`, which holds exactly the image's pixels in `format`,
+/// and checks the end marker.
+///
+/// Problems are handled by `problem` as in [`parse_chunks`]. Pixels left undecoded are written as `fill`, in which
+/// case the end marker is not checked.
+fn decode_chunks(bytes: &[u8], format: &PixelFormat, fill: &Pixel, out: &mut [u8], problem: &mut impl FnMut(QoiError) -> Result<(), QoiError>) -> Result<(), QoiError> {
+    let mut iter = bytes[HEADER_SIZE..].iter();
+    let pixels_seen = parse_chunks(&mut iter, out, format, problem)?;
+
+    let undecoded = &mut out[pixels_seen * format.bytes_per_pixel()..];
+    if !undecoded.is_empty() {
+        for values in undecoded.chunks_exact_mut(format.bytes_per_pixel()) {
+            format.write_pixel(&mut SliceOutput::new(values), fill);
+        }
+        return Ok(());
     }
 
+    match verify_ending(bytes, bytes.len() - iter.len()) {
+        Ok(()) => {Ok(())}
+        Err(err) => {problem(err)}
+    }
+}
 
-    return (metadata, decoded);
+/// Parses the header at the start of `bytes` without decoding any pixels.
+pub fn read_metadata(bytes: &[u8]) -> Result<ImgMetadata, QoiError> {
+    parse_metadata(&mut bytes.iter())
 }
 
-fn parse_metadata(iter: &mut Iter<u8>) -> ImgMetadata {
-    assert!(iter.len() >= 14);
-    let magic: String = iter.take(4).map(|x| char::from(*x)).collect();
-    assert_eq!(magic, "qoif");
+pub(crate) fn parse_metadata(iter: &mut Iter<u8>) -> Result<ImgMetadata, QoiError> {
+    if iter.len() < HEADER_SIZE {
+        return Err(QoiError::Truncated { offset: iter.len() });
+    }
+    for expected in b"qoif" {
+        if iter.next() != Some(expected) {
+            return Err(QoiError::InvalidMagic { offset: 0 });
+        }
+    }
 
     let width: u32 = parse_u32(iter);
     let height: u32 = parse_u32(iter);
 
-    let channels = match iter.next().unwrap() {
-        &3 => { Channels::RGB},
-        &4 => {Channels::RGBA},
-        &_ => {panic!("Invalid Header: Channels")}
+    let channels = match iter.next() {
+        Some(3) => {Channels::RGB},
+        Some(4) => {Channels::RGBA},
+        value => {return Err(QoiError::InvalidChannels { offset: 12, value: *value.unwrap_or(&0) })}
     };
-    let colorspace = match iter.next().unwrap() {
-        &0 => { Colorspace::SrgbLinearAlpha},
-        &1 => { Colorspace::AllLinearAlpha},
-        &_ => {panic!("Invalid Header: Colorspace")}
+    let colorspace = match iter.next() {
+        Some(0) => {Colorspace::SrgbLinearAlpha},
+        Some(1) => {Colorspace::AllLinearAlpha},
+        value => {return Err(QoiError::InvalidColorspace { offset: 13, value: *value.unwrap_or(&0) })}
     };
 
-    ImgMetadata {
+    let channels_per_pixel: usize = match channels {
+        Channels::RGB => {3}
+        Channels::RGBA => {4}
+    };
+    let decoded_size = (width as usize)
+        .checked_mul(height as usize)
+        .and_then(|pixels| pixels.checked_mul(channels_per_pixel));
+    if width == 0 || height == 0 || decoded_size.is_none() {
+        return Err(QoiError::InvalidDimensions { offset: 4, width, height });
+    }
+
+    Ok(ImgMetadata {
         width,
         height,
         channels,
         colorspace,
-    }
+    })
 }
 
 fn parse_u32(iter: &mut Iter<u8>) -> u32 {
-    let mut n: u32 = *iter.next().unwrap() as u32;
-    n = n << 8;
-    n += *iter.next().unwrap() as u32;
-    n = n << 8;
-    n += *iter.next().unwrap() as u32;
-    n = n << 8;
-    n += *iter.next().unwrap() as u32;
+    let mut n: u32 = 0;
+    for byte in iter.take(4) {
+        n <<= 8;
+        n += *byte as u32;
+    }
     n
 }
 
-fn parse_chunks(mut iter: &mut Iter<u8>, mut bytes: &mut Vec<u8>, include_alpha: bool) -> usize {
-    let mut pixels_seen: usize = 0;
+/// Decodes chunks into `out`, which holds exactly the image's pixels in `format`, until it is full or a problem stops
+/// decoding.
+///
+/// `iter` is expected to start right after the header; error offsets are relative to the start of the file. Each
+/// problem is passed to `problem`, which either returns it to give up or lets decoding go on as far as it can: a run
+/// past the last pixel is cut short, while an early end marker or a truncated chunk stops decoding. Returns the number
+/// of pixels written.
+fn parse_chunks(iter: &mut Iter<u8>, out: &mut [u8], format: &PixelFormat, problem: &mut impl FnMut(QoiError) -> Result<(), QoiError>) -> Result<usize, QoiError> {
+    parse_chunks_from(iter, HEADER_SIZE, &mut DecoderState::new(), out, format, problem)
+}
 
-    let mut prev_pixel = Pixel {
-        r: 0,
-        g: 0,
-        b: 0,
-        a: 255,
-    };
+/// Decodes chunks as in [`parse_chunks`], continuing from `state` with `iter` starting `start` bytes into the file.
+fn parse_chunks_from(iter: &mut Iter<u8>, start: usize, state: &mut DecoderState, out: &mut [u8], format: &PixelFormat, problem: &mut impl FnMut(QoiError) -> Result<(), QoiError>) -> Result<usize, QoiError> {
+    //the fast path stops short of the end marker and at anything unusual, which the checked loop handles
+    let (consumed, pixels_seen) = state.decode_fast(iter.as_slice(), out, format);
+    *iter = iter.as_slice()[consumed..].iter();
+    parse_chunks_checked(iter, start + consumed, state, out, pixels_seen, format, problem)
+}
 
-    let zero_pixel = Pixel {
-        r: 0,
-        g: 0,
-        b: 0,
-        a: 0,
-    };
+/// Decodes chunks as in [`parse_chunks_from`] one at a time, checking each, after the first `pixels_seen` pixels of
+/// `out` have been written.
+fn parse_chunks_checked(iter: &mut Iter<u8>, start: usize, state: &mut DecoderState, out: &mut [u8], mut pixels_seen: usize, format: &PixelFormat, problem: &mut impl FnMut(QoiError) -> Result<(), QoiError>) -> Result<usize, QoiError> {
+    let total_pixels = out.len() / format.bytes_per_pixel();
+    let chunks_len = iter.len();
+    let offset = |iter: &Iter<u8>| start + chunks_len - iter.len();
+    let bytes = &mut SliceOutput::new(&mut out[pixels_seen * format.bytes_per_pixel()..]);
+
+    while pixels_seen < total_pixels {
+        let tag_offset = offset(iter);
+        if iter.as_slice() == END_MARKER {
+            problem(QoiError::PixelCountMismatch { offset: tag_offset, expected: total_pixels, actual: pixels_seen })?;
+            break;
+        }
+        let tag = match iter.next() {
+            Some(tag) => tag,
+            None => {
+                problem(QoiError::Truncated { offset: tag_offset })?;
+                break;
+            }
+        };
+        let operation = parse_operation(tag);
 
-    let mut index: [Option<Pixel>; 64] = [None; 64];
-    index[super::encoder::calculate_index(&prev_pixel)] = Some(prev_pixel.clone());
-    index[super::encoder::calculate_index(&zero_pixel)] = Some(zero_pixel.clone());
+        if iter.len() < operation_payload_len(&operation) {
+            problem(QoiError::Truncated { offset: tag_offset })?;
+            break;
+        }
+        if operation == Operation::QoiOpRun && pixels_seen + run_length(tag) > total_pixels {
+            problem(QoiError::PixelCountMismatch { offset: tag_offset, expected: total_pixels, actual: pixels_seen + run_length(tag) })?;
+            //a run is never longer than 62 pixels, so the rest of the image fits in a shorter one
+            let remaining = (total_pixels - pixels_seen) as u8;
+            pixels_seen += state.write_chunk(bytes, &(QOI_OP_RUN << 6 | (remaining - 1)), iter, format);
+            continue;
+        }
 
+        pixels_seen += state.write_chunk(bytes, tag, iter, format);
+    }
 
+    Ok(pixels_seen)
+}
+
+/// Decodes the chunks from `offset` in `bytes` into `out`, which holds whole pixels in `format`, continuing from
+/// `state`, and returns the offset after the last chunk read.
+///
+/// The first `skip` pixels of the run at `offset` are left out, as they belong to earlier rows. When `more_follow`, a
+/// run past the end of `out` is cut short instead of being a problem, as the rest of it belongs to the next rows.
+#[cfg(feature = "alloc")]
+pub(crate) fn decode_segment(bytes: &[u8], mut offset: usize, skip: usize, state: &mut DecoderState, out: &mut [u8], format: &PixelFormat, more_follow: bool) -> Result<usize, QoiError> {
+    let total_pixels = out.len() / format.bytes_per_pixel();
+    let mut pixels_seen = 0;
+
+    if skip > 0 {
+        let tag = *bytes.get(offset).ok_or(QoiError::Truncated { offset })?;
+        if parse_operation(&tag) != Operation::QoiOpRun || run_length(&tag) <= skip {
+            return Err(QoiError::InvalidSeekTable { offset });
+        }
+        let remaining = (run_length(&tag) - skip).min(total_pixels);
+        if remaining > 0 {
+            pixels_seen = state.write_chunk(&mut SliceOutput::new(out), &(QOI_OP_RUN << 6 | (remaining - 1) as u8), &mut [].iter(), format);
+        }
+        offset += 1;
+    }
+
+    let chunks = bytes.get(offset..).ok_or(QoiError::Truncated { offset })?;
+    let mut iter = chunks.iter();
+    let mut stopped = None;
+    pixels_seen += parse_chunks_from(&mut iter, offset, state, &mut out[pixels_seen * format.bytes_per_pixel()..], format, &mut |err| match err {
+        QoiError::PixelCountMismatch { .. } if more_follow => {
+            stopped = Some(err);
+            Ok(())
+        }
+        err => {Err(err)}
+    })?;
+
+    //an early end marker stops decoding, while a run that was cut short still fills `out`
+    if pixels_seen < total_pixels {
+        return Err(stopped.unwrap_or(QoiError::Truncated { offset: bytes.len() }));
+    }
+    Ok(bytes.len() - iter.len())
+}
+
+/// The previous pixel and color index carried from one chunk to the next.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub(crate) struct DecoderState {
+    pub(crate) prev_pixel: Pixel,
+    //the spec starts the index zeroed, so empty slots hold the transparent black zero pixel
+    pub(crate) index: [Pixel; 64],
+}
+
+impl DecoderState {
+    pub(crate) fn new() -> DecoderState {
+        DecoderState {
+            prev_pixel: Pixel {
+                r: 0,
+                g: 0,
+                b: 0,
+                a: 255,
+            },
+            index: [Pixel { r: 0, g: 0, b: 0, a: 0 }; 64],
+        }
+    }
+
+    /// Decodes `chunks` into `out`, which holds whole pixels in `format`, while more input than the end marker remains.
+    /// Every chunk it reads is then complete and none of them can be the end marker, so it decodes the same chunks as
+    /// the checked loop.
+    ///
+    /// Stops early instead of reporting problems, at a run that would overflow `out`. Returns the number of input bytes
+    /// consumed and pixels written.
+    pub(crate) fn decode_fast(&mut self, chunks: &[u8], out: &mut [u8], format: &PixelFormat) -> (usize, usize) {
+        //the packed layouts get their own loops so writing a pixel doesn't branch on the format
+        match (format.layout, format.premultiplied) {
+            (PixelLayout::RGBA, false) => {self.decode_fast_with::<4>(chunks, out, |values, pixel| *values = [pixel.r, pixel.g, pixel.b, pixel.a])}
+            (PixelLayout::RGB, false) => {self.decode_fast_with::<3>(chunks, out, |values, pixel| *values = [pixel.r, pixel.g, pixel.b])}
+            _ => {
+                let write = |values: &mut [u8], pixel: &Pixel| format.write_pixel(&mut SliceOutput::new(values), pixel);
+                match format.bytes_per_pixel() {
+                    1 => {self.decode_fast_with::<1>(chunks, out, |values, pixel| write(values, pixel))}
+                    2 => {self.decode_fast_with::<2>(chunks, out, |values, pixel| write(values, pixel))}
+                    3 => {self.decode_fast_with::<3>(chunks, out, |values, pixel| write(values, pixel))}
+                    _ => {self.decode_fast_with::<4>(chunks, out, |values, pixel| write(values, pixel))}
+                }
+            }
+        }
+    }
+
+    /// Decodes as in [`DecoderState::decode_fast`], writing each pixel's `BYTES` bytes with `write_pixel`.
+    ///
+    /// The input is bounds checked once per chunk and the output once per pixel or run.
+    #[inline(always)]
+    fn decode_fast_with<const BYTES: usize>(&mut self, chunks: &[u8], out: &mut [u8], write_pixel: impl Fn(&mut [u8; BYTES], &Pixel)) -> (usize, usize) {
+        let total_pixels = out.len() / BYTES;
+        let mut consumed = 0;
+        let mut pixels_written = 0;
+        let mut prev_pixel = self.prev_pixel;
+
+        while pixels_written < total_pixels && chunks.len() - consumed > END_MARKER.len() {
+            let chunk: &[u8; 5] = chunks[consumed..consumed + 5].try_into().unwrap();
+            let tag = chunk[0];
+
+            let pixel = match tag {
+                QOI_OP_RGB => {
+                    consumed += 4;
+                    Pixel { r: chunk[1], g: chunk[2], b: chunk[3], a: prev_pixel.a }
+                }
+                QOI_OP_RGBA => {
+                    consumed += 5;
+                    Pixel { r: chunk[1], g: chunk[2], b: chunk[3], a: chunk[4] }
+                }
+                _ => match tag >> 6 {
+                    QOI_OP_INDEX => {
+                        consumed += 1;
+                        self.index[tag as usize]
+                    }
+                    QOI_OP_DIFF => {
+                        consumed += 1;
+                        Pixel {
+                            r: prev_pixel.r.wrapping_add((tag >> 4) & 0b11).wrapping_sub(2),
+                            g: prev_pixel.g.wrapping_add((tag >> 2) & 0b11).wrapping_sub(2),
+                            b: prev_pixel.b.wrapping_add(tag & 0b11).wrapping_sub(2),
+                            a: prev_pixel.a,
+                        }
+                    }
+                    QOI_OP_LUMA => {
+                        consumed += 2;
+                        let dg = (tag & 0b0011_1111).wrapping_sub(32);
+                        Pixel {
+                            r: prev_pixel.r.wrapping_add(dg).wrapping_add(chunk[1] >> 4).wrapping_sub(8),
+                            g: prev_pixel.g.wrapping_add(dg),
+                            b: prev_pixel.b.wrapping_add(dg).wrapping_add(chunk[1] & 0b1111).wrapping_sub(8),
+                            a: prev_pixel.a,
+                        }
+                    }
+                    _ => {
+                        let run_len = run_length(&tag);
+                        if pixels_written + run_len > total_pixels {
+                            break;
+                        }
+                        consumed += 1;
+
+                        let run = &mut out[pixels_written * BYTES..(pixels_written + run_len) * BYTES];
+                        let first: &mut [u8; BYTES] = (&mut run[..BYTES]).try_into().unwrap();
+                        write_pixel(first, &prev_pixel);
+                        simd::fill_repeats(run, BYTES);
+                        pixels_written += run_len;
+
+                        //the pixel is indexed like any other, which matters for the initial opaque black
+                        self.index[super::encoder::calculate_index(&prev_pixel)] = prev_pixel;
+                        continue;
+                    }
+                },
+            };
+
+            let values: &mut [u8; BYTES] = (&mut out[pixels_written * BYTES..(pixels_written + 1) * BYTES]).try_into().unwrap();
+            write_pixel(values, &pixel);
+            pixels_written += 1;
+
+            self.index[super::encoder::calculate_index(&pixel)] = pixel;
+            prev_pixel = pixel;
+        }
 
-    while let Some(tag) = iter.next() {
-        let operation = parse_operation(&tag);
+        self.prev_pixel = prev_pixel;
+        (consumed, pixels_written)
+    }
 
+    /// Writes the pixels for the chunk starting with `tag` and returns how many were written.
+    ///
+    /// `iter` must hold at least the chunk's payload.
+    pub(crate) fn write_chunk(&mut self, bytes: &mut impl Output, tag: &u8, iter: &mut Iter<u8>, format: &PixelFormat) -> usize {
+        let mut pixels_written = 1;
         let current_pixel: Pixel;
 
-        match operation {
-            Operation::QoiOpRgb => {current_pixel = write_op_rgb(&mut bytes, &mut iter, &prev_pixel.a, include_alpha);}
-            Operation::QoiOpRgba => {current_pixel = write_op_rgba(&mut bytes, &mut iter, include_alpha);}
-            Operation::QoiOpIndex => {current_pixel = write_op_index(&mut bytes, &tag, &index, include_alpha);}
-            Operation::QoiOpDiff => {current_pixel = write_op_diff(&mut bytes, &tag, &prev_pixel, include_alpha);}
-            Operation::QoiOpLuma => {current_pixel = write_op_luma(&mut bytes, &tag, &mut iter, &prev_pixel, include_alpha);}
+        match parse_operation(tag) {
+            Operation::QoiOpRgb => {current_pixel = write_op_rgb(bytes, iter, &self.prev_pixel.a, format);}
+            Operation::QoiOpRgba => {current_pixel = write_op_rgba(bytes, iter, format);}
+            Operation::QoiOpIndex => {current_pixel = write_op_index(bytes, tag, &self.index, format);}
+            Operation::QoiOpDiff => {current_pixel = write_op_diff(bytes, tag, &self.prev_pixel, format);}
+            Operation::QoiOpLuma => {current_pixel = write_op_luma(bytes, tag, iter, &self.prev_pixel, format);}
             Operation::QoiOpRun => {
-                let run_len = write_op_run(&mut bytes, &tag, &prev_pixel, include_alpha);
-                current_pixel = prev_pixel;
-                pixels_seen += run_len - 1;
+                pixels_written = write_op_run(bytes, tag, &self.prev_pixel, format);
+                current_pixel = self.prev_pixel;
             }
         }
 
-        index[super::encoder::calculate_index(&current_pixel)] = Some(current_pixel.clone());
-        prev_pixel = current_pixel;
+        self.index[super::encoder::calculate_index(&current_pixel)] = current_pixel;
+        self.prev_pixel = current_pixel;
 
-        pixels_seen += 1;
+        pixels_written
     }
-
-    pixels_seen
 }
 
-fn parse_operation(tag: &u8) -> Operation {
+pub(crate) fn parse_operation(tag: &u8) -> Operation {
     let tag_2 = tag >> 6;
     match *tag {
         QOI_OP_RGB => {Operation::QoiOpRgb}
@@ -129,78 +666,85 @@ fn parse_operation(tag: &u8) -> Operation {
                 QOI_OP_DIFF => {Operation::QoiOpDiff},
                 QOI_OP_LUMA => {Operation::QoiOpLuma},
                 QOI_OP_RUN => {Operation::QoiOpRun}
-                _ => {panic!("All 2 bit options are covered")}
+                _ => {unreachable!("All 2 bit options are covered")}
             }
         }
     }
 }
 
-fn verify_ending(vec: &Vec<u8>) {
-    assert!(vec.len() >= 8);
+/// Number of bytes following the tag byte for each operation.
+pub(crate) fn operation_payload_len(operation: &Operation) -> usize {
+    match operation {
+        Operation::QoiOpRgb => {3}
+        Operation::QoiOpRgba => {4}
+        Operation::QoiOpLuma => {1}
+        Operation::QoiOpIndex | Operation::QoiOpDiff | Operation::QoiOpRun => {0}
+    }
+}
+
+/// Checks that `bytes` holds exactly the end marker starting at `offset`, optionally followed by a seek table.
+pub(crate) fn verify_ending(bytes: &[u8], offset: usize) -> Result<(), QoiError> {
+    let remaining = &bytes[offset..];
 
-    let len = vec.len();
+    if remaining.starts_with(&END_MARKER) {
+        //a seek table appended to the file is only accepted when it fits the header
+        let trailer = &remaining[END_MARKER.len()..];
+        if !trailer.is_empty() && read_metadata(bytes).and_then(|metadata| seek::check_trailer(trailer, &metadata)).is_err() {
+            return Err(QoiError::TrailingBytes { offset: offset + END_MARKER.len() });
+        }
+        return Ok(());
+    }
 
-    for i in 0..7 {
-        assert_eq!(0, vec[len - 8 + i]);
+    if remaining.ends_with(&END_MARKER) {
+        return Err(QoiError::TrailingBytes { offset });
     }
 
-    assert_eq!(1, vec[len - 1]);
+    Err(QoiError::MissingEndMarker { offset })
 }
 
-fn write_op_rgb(bytes: &mut Vec<u8>, iter: &mut Iter<u8>, alpha: &u8, include_alpha: bool) -> Pixel {
+fn write_op_rgb(bytes: &mut impl Output, iter: &mut Iter<u8>, alpha: &u8, format: &PixelFormat) -> Pixel {
     let r = iter.next().unwrap();
     let g = iter.next().unwrap();
     let b = iter.next().unwrap();
     let a = alpha;
 
-    bytes.push(*r);
-    bytes.push(*g);
-    bytes.push(*b);
-
-    if include_alpha { bytes.push(*a);};
-
-    Pixel {
+    let pixel = Pixel {
         r: *r,
         g: *g,
         b: *b,
         a: *a,
-    }
+    };
+    format.write_pixel(bytes, &pixel);
+
+    pixel
 }
 
-fn write_op_rgba(bytes: &mut Vec<u8>, iter: &mut Iter<u8>, include_alpha: bool) -> Pixel {
+fn write_op_rgba(bytes: &mut impl Output, iter: &mut Iter<u8>, format: &PixelFormat) -> Pixel {
     let r = iter.next().unwrap();
     let g = iter.next().unwrap();
     let b = iter.next().unwrap();
     let a = iter.next().unwrap();
 
-    bytes.push(*r);
-    bytes.push(*g);
-    bytes.push(*b);
-
-    if include_alpha { bytes.push(*a);};
-
-    Pixel {
+    let pixel = Pixel {
         r: *r,
         g: *g,
         b: *b,
         a: *a,
-    }
+    };
+    format.write_pixel(bytes, &pixel);
+
+    pixel
 }
 
-fn write_op_index(bytes: &mut Vec<u8>, tag: &u8, index: &[Option<Pixel>], include_alpha: bool) -> Pixel {
-    let pixel = index[*tag as usize].unwrap();
-    bytes.push(pixel.r);
-    bytes.push(pixel.g);
-    bytes.push(pixel.b);
+fn write_op_index(bytes: &mut impl Output, tag: &u8, index: &[Pixel], format: &PixelFormat) -> Pixel {
+    let pixel = index[*tag as usize];
+    format.write_pixel(bytes, &pixel);
 
-    if include_alpha {
-        bytes.push(pixel.a);
-    }
     pixel
 }
 
-fn write_op_diff(bytes: &mut Vec<u8>, tag: &u8, prev_pixel: &Pixel, include_alpha: bool) -> Pixel {
-    let mut current_pixel = prev_pixel.clone();
+fn write_op_diff(bytes: &mut impl Output, tag: &u8, prev_pixel: &Pixel, format: &PixelFormat) -> Pixel {
+    let mut current_pixel = *prev_pixel;
 
     let dr = (0b_00_11_00_00 & *tag) >> 4;
     let dg = (0b_00_00_11_00 & *tag) >> 2;
@@ -214,16 +758,12 @@ fn write_op_diff(bytes: &mut Vec<u8>, tag: &u8, prev_pixel: &Pixel, include_alph
     current_pixel.g = u8::wrapping_sub(g, 2);
     current_pixel.b = u8::wrapping_sub(b, 2);
 
-    bytes.push(current_pixel.r);
-    bytes.push(current_pixel.g);
-    bytes.push(current_pixel.b);
-
-    if include_alpha { bytes.push(current_pixel.a);}
+    format.write_pixel(bytes, &current_pixel);
 
     current_pixel
 }
 
-fn write_op_luma(bytes: &mut Vec<u8>, tag: &u8, iter: &mut Iter<u8>, prev_pixel: &Pixel, include_alpha: bool) -> Pixel{
+fn write_op_luma(bytes: &mut impl Output, tag: &u8, iter: &mut Iter<u8>, prev_pixel: &Pixel, format: &PixelFormat) -> Pixel{
     let dg = *tag & 0b00_111111;
 
     let byte2 = iter.next().unwrap();
@@ -245,37 +785,38 @@ fn write_op_luma(bytes: &mut Vec<u8>, tag: &u8, iter: &mut Iter<u8>, prev_pixel:
     let b = u8::wrapping_add(prev_pixel.b, db);
     let a = prev_pixel.a;
 
-    bytes.push(r);
-    bytes.push(g);
-    bytes.push(b);
-    if include_alpha { bytes.push(a);}
-
-    Pixel {
+    let pixel = Pixel {
         r,
         g,
         b,
         a,
-    }
+    };
+    format.write_pixel(bytes, &pixel);
+
+    pixel
+
+}
 
+pub(crate) fn run_length(tag: &u8) -> usize {
+    ((*tag & 0b0011_1111) + 1) as usize
 }
 
-fn write_op_run(bytes: &mut Vec<u8>, tag: &u8, prev_pixel: &Pixel, include_alpha: bool) -> usize {
-    let run_len = (*tag & 0b_00_111111) + 1;
+fn write_op_run(bytes: &mut impl Output, tag: &u8, prev_pixel: &Pixel, format: &PixelFormat) -> usize {
+    let run_len = run_length(tag);
 
     for _ in 0..run_len {
-        bytes.push(prev_pixel.r);
-        bytes.push(prev_pixel.g);
-        bytes.push(prev_pixel.b);
-        if include_alpha { bytes.push(prev_pixel.a);}
+        format.write_pixel(bytes, prev_pixel);
     }
 
-    run_len as usize
+    run_len
 }
 
-#[cfg(test)]
+#[cfg(all(test, feature = "alloc"))]
+#[allow(clippy::identity_op, clippy::useless_vec)]
 mod tests {
     use crate::{Channels, Colorspace, Operation, QOI_OP_DIFF, QOI_OP_INDEX, QOI_OP_LUMA, QOI_OP_RGB, QOI_OP_RGBA};
     use crate::encoder::calculate_index;
+    use crate::test_util::{header_bytes, metadata};
     use super::*;
 
     #[test]
@@ -290,10 +831,7 @@ mod tests {
         crate::encoder::add_header(&mut bytes, &metadata);
         crate::encoder::add_end_marker(&mut bytes);
 
-        let (returned_metadata, encoded) = decode(&bytes);
-
-        assert_eq!(metadata, returned_metadata);
-        assert_eq!(encoded, vec![]);
+        assert_eq!(decode(&bytes), Err(QoiError::InvalidDimensions { offset: 4, width: 0, height: 0 }));
     }
 
     #[test]
@@ -310,11 +848,298 @@ mod tests {
 
         let mut iter = bytes.iter();
 
-        let returned_metadata = parse_metadata(&mut iter);
+        let returned_metadata = parse_metadata(&mut iter).unwrap();
 
         assert_eq!(metadata, returned_metadata);
     }
 
+    #[test]
+    fn parse_metadata_invalid_magic() {
+        let mut bytes = header_bytes(1, 1);
+        bytes[2] = b'x';
+        assert_eq!(parse_metadata(&mut bytes.iter()), Err(QoiError::InvalidMagic { offset: 0 }));
+    }
+
+    #[test]
+    fn parse_metadata_invalid_channels() {
+        let mut bytes = header_bytes(1, 1);
+        bytes[12] = 5;
+        assert_eq!(parse_metadata(&mut bytes.iter()), Err(QoiError::InvalidChannels { offset: 12, value: 5 }));
+    }
+
+    #[test]
+    fn parse_metadata_invalid_colorspace() {
+        let mut bytes = header_bytes(1, 1);
+        bytes[13] = 2;
+        assert_eq!(parse_metadata(&mut bytes.iter()), Err(QoiError::InvalidColorspace { offset: 13, value: 2 }));
+    }
+
+    #[test]
+    fn parse_metadata_zero_dimension() {
+        let bytes = header_bytes(10, 0);
+        assert_eq!(parse_metadata(&mut bytes.iter()), Err(QoiError::InvalidDimensions { offset: 4, width: 10, height: 0 }));
+    }
+
+    #[test]
+    fn parse_metadata_truncated() {
+        let bytes = header_bytes(1, 1);
+        assert_eq!(parse_metadata(&mut bytes[..10].iter()), Err(QoiError::Truncated { offset: 10 }));
+    }
+
+    #[test]
+    fn decode_truncated_chunk() {
+        let mut bytes = header_bytes(2, 1);
+        bytes.extend(vec![QOI_OP_RGB, 17, 18, 200]);
+        bytes.extend(vec![QOI_OP_RGB, 6, 100]);
+
+        assert_eq!(decode(&bytes), Err(QoiError::Truncated { offset: 18 }));
+    }
+
+    #[test]
+    fn decode_missing_end_marker() {
+        let mut bytes = header_bytes(1, 1);
+        bytes.extend(vec![QOI_OP_RGB, 17, 18, 200]);
+        bytes.extend(vec![0, 0, 0, 1]);
+
+        assert_eq!(decode(&bytes), Err(QoiError::MissingEndMarker { offset: 18 }));
+    }
+
+    #[test]
+    fn decode_too_few_pixels() {
+        let mut bytes = header_bytes(3, 1);
+        bytes.extend(vec![QOI_OP_RGB, 17, 18, 200]);
+        crate::encoder::add_end_marker(&mut bytes);
+
+        assert_eq!(decode(&bytes), Err(QoiError::PixelCountMismatch { offset: 18, expected: 3, actual: 1 }));
+    }
+
+    #[test]
+    fn decode_run_past_end() {
+        let mut bytes = header_bytes(3, 1);
+        bytes.extend(vec![QOI_OP_RGB, 17, 18, 200]);
+        bytes.push((QOI_OP_RUN << 6) + 2);
+        crate::encoder::add_end_marker(&mut bytes);
+
+        assert_eq!(decode(&bytes), Err(QoiError::PixelCountMismatch { offset: 18, expected: 3, actual: 4 }));
+    }
+
+    #[test]
+    fn decode_trailing_bytes() {
+        let mut bytes = header_bytes(1, 1);
+        bytes.extend(vec![QOI_OP_RGB, 17, 18, 200]);
+        crate::encoder::add_end_marker(&mut bytes);
+        bytes.push(3);
+
+        assert_eq!(decode(&bytes), Err(QoiError::TrailingBytes { offset: 26 }));
+    }
+
+    #[test]
+    fn limits_default_pixel_ceiling() {
+        let limits = DecodeLimits::default();
+        assert_eq!(limits.check(&parse_metadata(&mut header_bytes(20_000, 20_000).iter()).unwrap(), &PixelLayout::RGBA), Ok(()));
+
+        //a 14 byte header is enough to ask for gigabytes, so it must fail before any allocation
+        let mut bytes = header_bytes(20_001, 20_000);
+        bytes.extend(END_MARKER);
+        assert_eq!(decode(&bytes), Err(QoiError::LimitExceeded { offset: 4, width: 20_001, height: 20_000, limit: Limit::MaxPixels(QOI_PIXELS_MAX) }));
+    }
+
+    #[test]
+    fn limits_each_bound() {
+        let metadata = parse_metadata(&mut header_bytes(100, 50).iter()).unwrap();
+        let limits = DecodeLimits { max_width: 100, max_height: 50, max_pixels: 5000, max_alloc_bytes: 15000 };
+        assert_eq!(limits.check(&metadata, &PixelLayout::RGB), Ok(()));
+
+        let exceeded = |offset, limit| Err(QoiError::LimitExceeded { offset, width: 100, height: 50, limit });
+        assert_eq!(DecodeLimits { max_width: 99, ..limits }.check(&metadata, &PixelLayout::RGB), exceeded(4, Limit::MaxWidth(99)));
+        assert_eq!(DecodeLimits { max_height: 49, ..limits }.check(&metadata, &PixelLayout::RGB), exceeded(8, Limit::MaxHeight(49)));
+        assert_eq!(DecodeLimits { max_pixels: 4999, ..limits }.check(&metadata, &PixelLayout::RGB), exceeded(4, Limit::MaxPixels(4999)));
+        //the allocation depends on the output layout
+        assert_eq!(limits.check(&metadata, &PixelLayout::RGBA), exceeded(4, Limit::MaxAllocBytes(15000)));
+        assert_eq!(limits.check(&metadata, &PixelLayout::L8), Ok(()));
+    }
+
+    #[test]
+    fn decode_options_limits() {
+        let mut bytes = header_bytes(2, 1);
+        bytes.extend(vec![QOI_OP_RGB, 1, 2, 3, 0b11_000000]);
+        bytes.extend(END_MARKER);
+
+        let options = DecodeOptions::new().with_limits(DecodeLimits { max_alloc_bytes: 7, ..DecodeLimits::default() });
+        assert_eq!(options.decode(&bytes).map(|(_, pixels)| pixels), Ok(vec![1, 2, 3, 1, 2, 3]));
+
+        let options = options.with_layout(PixelLayout::RGBA);
+        assert_eq!(options.decode(&bytes), Err(QoiError::LimitExceeded { offset: 4, width: 2, height: 1, limit: Limit::MaxAllocBytes(7) }));
+        assert_eq!(options.decode_into(&bytes, &mut [0; 8]), Err(QoiError::LimitExceeded { offset: 4, width: 2, height: 1, limit: Limit::MaxAllocBytes(7) }));
+    }
+
+    #[test]
+    fn fast_path_leaves_tail() {
+        let chunks = [0b01_101010; 30];
+        let mut out = [0; 30 * 3];
+        let (consumed, pixels) = DecoderState::new().decode_fast(&chunks, &mut out, &PixelFormat::from(PixelLayout::RGB));
+        assert_eq!((consumed, pixels), (30 - END_MARKER.len(), 30 - END_MARKER.len()));
+
+        //a run that would overflow the output is left for the checked loop to report
+        let mut chunks = vec![QOI_OP_RGB, 1, 2, 3, 0b11_000010];
+        chunks.extend([0; 10]);
+        let (consumed, pixels) = DecoderState::new().decode_fast(&chunks, &mut out[..6], &PixelFormat::from(PixelLayout::RGB));
+        assert_eq!((consumed, pixels), (4, 1));
+    }
+
+    #[test]
+    fn fast_path_matches_chunk_by_chunk() {
+        let mut chunks = vec![0b11_000001, 53, QOI_OP_RGBA, 250, 3, 9, 128, 0b01_000111, 0b10_011111, 0b1000_0111];
+        chunks.extend([0b11_111101, QOI_OP_RGB, 0, 255, 7, 0b01_111111, 53, 0b11_000000, 0b10_000000, 0b1111_0000]);
+        let total_pixels = 2 + 1 + 1 + 1 + 62 + 1 + 1 + 1 + 1 + 1 + 1;
+
+        let layouts = [PixelLayout::RGB, PixelLayout::RGBA, PixelLayout::BGR, PixelLayout::BGRA, PixelLayout::ARGB, PixelLayout::ABGR, PixelLayout::L8, PixelLayout::LA8];
+        for format in layouts.into_iter().flat_map(|layout| [false, true].map(|premultiplied| PixelFormat { layout, premultiplied })) {
+            let mut padded = chunks.clone();
+            padded.extend(END_MARKER);
+            let mut fast = vec![0; total_pixels * format.bytes_per_pixel()];
+            let (consumed, pixels) = DecoderState::new().decode_fast(&padded, &mut fast, &format);
+            assert_eq!((consumed, pixels), (chunks.len(), total_pixels));
+
+            let mut expected = Vec::new();
+            let mut state = DecoderState::new();
+            let mut iter = chunks.iter();
+            while let Some(tag) = iter.next() {
+                state.write_chunk(&mut expected, tag, &mut iter, &format);
+            }
+            assert_eq!(fast, expected, "{:?}", format);
+        }
+    }
+
+    #[test]
+    fn fast_path_errors_match_checked_loop() {
+        let pixels: Vec<u8> = (0..40u32).flat_map(|i| {
+            let value = if i % 10 < 4 {0} else {(i * 37 % 256) as u8};
+            [value, value.wrapping_add(1), 255 - value, if i % 13 == 0 {100} else {255}]
+        }).collect();
+        let encoded = crate::encode(&pixels, &metadata(8, 5, Channels::RGBA));
+        let format = PixelFormat::from(PixelLayout::RGBA);
+
+        let decode = |len: usize, total_pixels: usize, checked: bool| {
+            let mut out = vec![0; total_pixels * 4];
+            let mut iter = encoded[HEADER_SIZE..len].iter();
+            let mut problems = Vec::new();
+            let mut report = |err| {
+                problems.push(err);
+                Ok(())
+            };
+            let result = if checked {
+                parse_chunks_checked(&mut iter, HEADER_SIZE, &mut DecoderState::new(), &mut out, 0, &format, &mut report)
+            } else {
+                parse_chunks(&mut iter, &mut out, &format, &mut report)
+            };
+            (result, out, iter.len(), problems)
+        };
+
+        //every truncation of the chunks, decoded as fewer, as many and more pixels than they hold
+        for len in HEADER_SIZE..=encoded.len() {
+            for total_pixels in [30, 40, 50] {
+                assert_eq!(decode(len, total_pixels, false), decode(len, total_pixels, true), "{len} bytes as {total_pixels} pixels");
+            }
+        }
+    }
+
+    #[test]
+    fn leading_run_is_indexed() {
+        //opaque black hashes to slot 53 once a run has repeated it
+        let mut bytes = header_bytes(3, 1);
+        bytes.extend([0b11_000001, 53]);
+        bytes.extend(END_MARKER);
+        assert_eq!(decode(&bytes).map(|(_, pixels)| pixels), Ok(vec![0; 9]));
+
+        let mut bytes = header_bytes(2, 1);
+        bytes.extend([QOI_OP_RGB, 9, 9, 9, 53]);
+        bytes.extend(END_MARKER);
+        assert_eq!(decode(&bytes).map(|(_, pixels)| pixels), Ok(vec![9, 9, 9, 0, 0, 0]));
+    }
+
+    #[test]
+    fn strict_rejects_problems() {
+        let options = DecodeOptions::new().with_strictness(Strictness::Strict);
+
+        let mut bytes = header_bytes(2, 1);
+        bytes.extend(vec![QOI_OP_RGB, 1, 2, 3, 0b11_000010]);
+        bytes.extend(END_MARKER);
+        assert_eq!(options.decode(&bytes), Err(QoiError::PixelCountMismatch { offset: 18, expected: 2, actual: 4 }));
+
+        let mut bytes = header_bytes(2, 1);
+        bytes.extend(vec![QOI_OP_RGB, 1, 2, 3, 0b11_000000, 9]);
+        bytes.extend(END_MARKER);
+        assert_eq!(options.decode_with_problems(&bytes), Err(QoiError::TrailingBytes { offset: 19 }));
+        assert_eq!(options.decode_with_problems(&bytes[..19]), Err(QoiError::MissingEndMarker { offset: 19 }));
+    }
+
+    #[test]
+    fn lenient_fills_truncated_image() {
+        let fill = Pixel::new(9, 8, 7, 6);
+        let options = DecodeOptions::new().with_layout(PixelLayout::RGBA).with_strictness(Strictness::Lenient { fill });
+
+        let mut bytes = header_bytes(3, 1);
+        bytes.extend(vec![QOI_OP_RGB, 1, 2, 3, QOI_OP_RGB, 4]);
+        let (_, pixels, problems) = options.decode_with_problems(&bytes).unwrap();
+        assert_eq!(pixels, vec![1, 2, 3, 255, 9, 8, 7, 6, 9, 8, 7, 6]);
+        assert_eq!(problems, vec![QoiError::Truncated { offset: 18 }]);
+
+        //an early end marker stops decoding too
+        let mut bytes = header_bytes(3, 1);
+        bytes.extend(vec![QOI_OP_RGB, 1, 2, 3]);
+        bytes.extend(END_MARKER);
+        let (_, pixels, problems) = options.decode_with_problems(&bytes).unwrap();
+        assert_eq!(pixels, vec![1, 2, 3, 255, 9, 8, 7, 6, 9, 8, 7, 6]);
+        assert_eq!(problems, vec![QoiError::PixelCountMismatch { offset: 18, expected: 3, actual: 1 }]);
+
+        let mut out = [0; 9];
+        let options = options.with_layout(PixelLayout::BGR);
+        assert_eq!(options.decode_into(&bytes[..18], &mut out), Ok((read_metadata(&bytes).unwrap(), 9)));
+        assert_eq!(out, [3, 2, 1, 7, 8, 9, 7, 8, 9]);
+    }
+
+    #[test]
+    fn lenient_reports_every_problem() {
+        let options = DecodeOptions::new().with_strictness(Strictness::Lenient { fill: Pixel::rgb(0, 0, 0) });
+
+        //the run is cut short at the last pixel, and the byte after it is trailing
+        let mut bytes = header_bytes(2, 1);
+        bytes.extend(vec![QOI_OP_RGB, 1, 2, 3, 0b11_000010, 9]);
+        bytes.extend(END_MARKER);
+        let (_, pixels, problems) = options.decode_with_problems(&bytes).unwrap();
+        assert_eq!(pixels, vec![1, 2, 3, 1, 2, 3]);
+        assert_eq!(problems, vec![
+            QoiError::PixelCountMismatch { offset: 18, expected: 2, actual: 4 },
+            QoiError::TrailingBytes { offset: 19 },
+        ]);
+        assert_eq!(options.decode(&bytes).map(|(_, pixels)| pixels), Ok(vec![1, 2, 3, 1, 2, 3]));
+
+        let (_, pixels, problems) = options.decode_with_problems(&bytes[..19]).unwrap();
+        assert_eq!(pixels, vec![1, 2, 3, 1, 2, 3]);
+        assert_eq!(problems[1], QoiError::MissingEndMarker { offset: 19 });
+
+        //header errors are never skipped
+        assert_eq!(options.decode_with_problems(&bytes[..10]), Err(QoiError::Truncated { offset: 10 }));
+    }
+
+    #[test]
+    fn read_metadata_only_header() {
+        let metadata = ImgMetadata {
+            width: 640,
+            height: 480,
+            channels: Channels::RGBA,
+            colorspace: Colorspace::AllLinearAlpha,
+        };
+        let mut bytes = Vec::new();
+        crate::encoder::add_header(&mut bytes, &metadata);
+        //chunks aren't looked at, so garbage after the header is fine
+        bytes.extend(vec![1, 2, 3]);
+
+        assert_eq!(read_metadata(&bytes), Ok(metadata));
+        assert_eq!(read_metadata(&bytes[..13]), Err(QoiError::Truncated { offset: 13 }));
+    }
+
     #[test]
     fn parse_u32_test() {
         let mut v: Vec<u8> = Vec::with_capacity(4);
@@ -342,8 +1167,8 @@ mod tests {
         let expected = vec![50, 80, 23,
                             100, 17, 88];
 
-        let mut bytes = Vec::new();
-        parse_chunks(&mut op.iter(), &mut bytes, false);
+        let mut bytes = vec![0; 6];
+        parse_chunks(&mut op.iter(), &mut bytes, &PixelFormat::from(PixelLayout::RGB), &mut Err).unwrap();
 
         assert_eq!(expected, bytes)
     }
@@ -355,8 +1180,8 @@ mod tests {
         let expected = vec![50, 80, 23, 200,
                             100, 17, 88, 200];
 
-        let mut bytes = Vec::new();
-        parse_chunks(&mut op.iter(), &mut bytes, true);
+        let mut bytes = vec![0; 8];
+        parse_chunks(&mut op.iter(), &mut bytes, &PixelFormat::from(PixelLayout::RGBA), &mut Err).unwrap();
 
         assert_eq!(expected, bytes)
     }
@@ -378,8 +1203,32 @@ mod tests {
                             100, 180, 0, 55,
                             50, 80, 23, 200];
 
-        let mut bytes = Vec::new();
-        parse_chunks(&mut op.iter(), &mut bytes, true);
+        let mut bytes = vec![0; 12];
+        parse_chunks(&mut op.iter(), &mut bytes, &PixelFormat::from(PixelLayout::RGBA), &mut Err).unwrap();
+
+        assert_eq!(expected, bytes)
+    }
+
+    #[test]
+    fn parse_op_index_chunk_initial_state() {
+        let black = Pixel {
+            r: 0,
+            g: 0,
+            b: 0,
+            a: 255,
+        };
+        let index = calculate_index(&black) as u8;
+
+        //nothing has been written to the index yet, so every slot holds transparent black
+        let op = vec![QOI_OP_RGB, 255, 255, 255,
+                      index,
+                      0];
+        let expected = vec![255, 255, 255, 255,
+                            0, 0, 0, 0,
+                            0, 0, 0, 0];
+
+        let mut bytes = vec![0; 12];
+        parse_chunks(&mut op.iter(), &mut bytes, &PixelFormat::from(PixelLayout::RGBA), &mut Err).unwrap();
 
         assert_eq!(expected, bytes)
     }
@@ -401,8 +1250,8 @@ mod tests {
         let expected = vec![50, 80, 23, 200,
                             49, 78, 24, 200];
 
-        let mut bytes = Vec::new();
-        parse_chunks(&mut op.iter(), &mut bytes, true);
+        let mut bytes = vec![0; 8];
+        parse_chunks(&mut op.iter(), &mut bytes, &PixelFormat::from(PixelLayout::RGBA), &mut Err).unwrap();
 
         assert_eq!(expected, bytes)
     }
@@ -428,8 +1277,8 @@ mod tests {
         let expected = vec![230, 255, 23, 200,
                             250, 24, 48, 200];
 
-        let mut bytes = Vec::new();
-        parse_chunks(&mut op.iter(), &mut bytes, true);
+        let mut bytes = vec![0; 8];
+        parse_chunks(&mut op.iter(), &mut bytes, &PixelFormat::from(PixelLayout::RGBA), &mut Err).unwrap();
 
         assert_eq!(expected, bytes)
     }
@@ -456,8 +1305,8 @@ mod tests {
             expected.push(pixel.a);
         }
 
-        let mut bytes = Vec::new();
-        parse_chunks(&mut op.iter(), &mut bytes, true);
+        let mut bytes = vec![0; 24];
+        parse_chunks(&mut op.iter(), &mut bytes, &PixelFormat::from(PixelLayout::RGBA), &mut Err).unwrap();
 
         assert_eq!(expected, bytes)
     }
@@ -476,7 +1325,7 @@ mod tests {
         bytes.extend(vec![QOI_OP_RGB, 6, 100, 50]);
         crate::encoder::add_end_marker(&mut bytes);
 
-        let (returned_metadata, encoded) = decode(&bytes);
+        let (returned_metadata, encoded) = decode(&bytes).unwrap();
 
         assert_eq!(metadata, returned_metadata);
         //alpha is unchanged from default 255
@@ -499,7 +1348,7 @@ mod tests {
         bytes.extend(vec![QOI_OP_RGBA, 7, 101, 51, 80]);
         crate::encoder::add_end_marker(&mut bytes);
 
-        let (returned_metadata, encoded) = decode(&bytes);
+        let (returned_metadata, encoded) = decode(&bytes).unwrap();
 
         assert_eq!(metadata, returned_metadata);
         //alpha's original default value is 255
@@ -533,7 +1382,7 @@ mod tests {
         bytes.push(index);
         crate::encoder::add_end_marker(&mut bytes);
 
-        let (returned_metadata, encoded) = decode(&bytes);
+        let (returned_metadata, encoded) = decode(&bytes).unwrap();
 
         assert_eq!(metadata, returned_metadata);
         //alpha's original default value is 255
@@ -568,7 +1417,7 @@ mod tests {
         bytes.push(diff_op);
         crate::encoder::add_end_marker(&mut bytes);
 
-        let (returned_metadata, encoded) = decode(&bytes);
+        let (returned_metadata, encoded) = decode(&bytes).unwrap();
 
         assert_eq!(metadata, returned_metadata);
         //alpha's original default value is 255
@@ -608,7 +1457,7 @@ mod tests {
         bytes.extend(vec![luma_op, byte2]);
         crate::encoder::add_end_marker(&mut bytes);
 
-        let (returned_metadata, encoded) = decode(&bytes);
+        let (returned_metadata, encoded) = decode(&bytes).unwrap();
 
         assert_eq!(metadata, returned_metadata);
         //alpha's original default value is 255
@@ -643,7 +1492,7 @@ mod tests {
         bytes.push(op_run);
         crate::encoder::add_end_marker(&mut bytes);
 
-        let (returned_metadata, encoded) = decode(&bytes);
+        let (returned_metadata, encoded) = decode(&bytes).unwrap();
 
         assert_eq!(metadata, returned_metadata);
 
@@ -689,21 +1538,28 @@ mod tests {
     #[test]
     fn verify_ending_success() {
         let v: Vec<u8> = vec![12, 8, 7, 0, 0, 0, 0, 0, 0, 0, 1];
-        verify_ending(&v);
+        assert_eq!(verify_ending(&v, 3), Ok(()));
     }
 
     #[test]
-    #[should_panic]
     fn verify_ending_fail() {
         let v: Vec<u8> = vec![12, 8, 7, 0, 0, 0, 0, 5, 0, 0, 0];
-        verify_ending(&v);
+        assert_eq!(verify_ending(&v, 3), Err(QoiError::MissingEndMarker { offset: 3 }));
     }
 
     #[test]
-    #[should_panic]
     fn verify_ending_too_short() {
         let v: Vec<u8> = vec![0, 0, 0, 1];
-        verify_ending(&v);
+        assert_eq!(verify_ending(&v, 0), Err(QoiError::MissingEndMarker { offset: 0 }));
+    }
+
+    #[test]
+    fn verify_ending_trailing_bytes() {
+        let v: Vec<u8> = vec![0, 0, 0, 0, 0, 0, 0, 1, 7];
+        assert_eq!(verify_ending(&v, 0), Err(QoiError::TrailingBytes { offset: 8 }));
+
+        let v: Vec<u8> = vec![7, 0, 0, 0, 0, 0, 0, 0, 1];
+        assert_eq!(verify_ending(&v, 0), Err(QoiError::TrailingBytes { offset: 0 }));
     }
 
     #[test]
@@ -726,7 +1582,7 @@ mod tests {
             a: 50,
         };
 
-        let current_pixel = write_op_rgb(&mut bytes, &mut op.iter(), &50, true);
+        let current_pixel = write_op_rgb(&mut bytes, &mut op.iter(), &50, &PixelFormat::from(PixelLayout::RGBA));
 
         assert_eq!(bytes, expected);
         assert_eq!(current_pixel, expected_pixel);
@@ -747,7 +1603,7 @@ mod tests {
             a: 50,
         };
 
-        let current_pixel = write_op_rgb(&mut bytes, &mut op.iter(), &50, false);
+        let current_pixel = write_op_rgb(&mut bytes, &mut op.iter(), &50, &PixelFormat::from(PixelLayout::RGB));
 
         assert_eq!(bytes, expected);
         assert_eq!(current_pixel, expected_pixel);
@@ -768,7 +1624,7 @@ mod tests {
             a: 40,
         };
 
-        let current_pixel = write_op_rgba(&mut bytes, &mut op.iter(), true);
+        let current_pixel = write_op_rgba(&mut bytes, &mut op.iter(), &PixelFormat::from(PixelLayout::RGBA));
 
         assert_eq!(bytes, expected);
         assert_eq!(current_pixel, expected_pixel);
@@ -789,7 +1645,7 @@ mod tests {
             a: 40,
         };
 
-        let current_pixel = write_op_rgba(&mut bytes, &mut op.iter(), false);
+        let current_pixel = write_op_rgba(&mut bytes, &mut op.iter(), &PixelFormat::from(PixelLayout::RGB));
 
         assert_eq!(bytes, expected);
         assert_eq!(current_pixel, expected_pixel);
@@ -808,14 +1664,14 @@ mod tests {
             a: 40,
         };
 
-        let mut index: [Option<Pixel>; 64] = [None; 64];
+        let mut index = [Pixel { r: 0, g: 0, b: 0, a: 0 }; 64];
         let i = calculate_index(&expected_pixel);
-        index[i] = Some(expected_pixel.clone());
+        index[i] = expected_pixel;
 
         //since QOI_OP_INDEX's 2 bit tag is 0b00, the entire instruction is simply the index number
         let tag = i as u8;
 
-        let current_pixel = write_op_index(&mut bytes, &tag, &index, true);
+        let current_pixel = write_op_index(&mut bytes, &tag, &index, &PixelFormat::from(PixelLayout::RGBA));
 
         assert_eq!(bytes, expected);
         assert_eq!(current_pixel, expected_pixel);
@@ -834,14 +1690,14 @@ mod tests {
             a: 40,
         };
 
-        let mut index: [Option<Pixel>; 64] = [None; 64];
+        let mut index = [Pixel { r: 0, g: 0, b: 0, a: 0 }; 64];
         let i = calculate_index(&expected_pixel);
-        index[i] = Some(expected_pixel.clone());
+        index[i] = expected_pixel;
 
         //since QOI_OP_INDEX's 2 bit tag is 0b00, the entire instruction is simply the index number
         let tag = i as u8;
 
-        let current_pixel = write_op_index(&mut bytes, &tag, &index, false);
+        let current_pixel = write_op_index(&mut bytes, &tag, &index, &PixelFormat::from(PixelLayout::RGB));
 
         assert_eq!(bytes, expected);
         assert_eq!(current_pixel, expected_pixel);
@@ -869,7 +1725,7 @@ mod tests {
         //QOI_OP_DIFF: 01-tag, dr: 1 dg: -2 db: 0
         let op: u8 = 0b01_11_00_10;
 
-        let current_pixel = write_op_diff(&mut bytes, &op, &previous_pixel, true);
+        let current_pixel = write_op_diff(&mut bytes, &op, &previous_pixel, &PixelFormat::from(PixelLayout::RGBA));
 
         assert_eq!(bytes, expected);
         assert_eq!(current_pixel, expected_pixel);
@@ -897,7 +1753,7 @@ mod tests {
         //QOI_OP_DIFF: 01-tag, dr: 1 dg: -2 db: 0
         let op: u8 = 0b01_11_00_10;
 
-        let current_pixel = write_op_diff(&mut bytes, &op, &previous_pixel, false);
+        let current_pixel = write_op_diff(&mut bytes, &op, &previous_pixel, &PixelFormat::from(PixelLayout::RGB));
 
         assert_eq!(bytes, expected);
         assert_eq!(current_pixel, expected_pixel);
@@ -939,7 +1795,7 @@ mod tests {
 
         let ops = vec![byte2];
 
-        let current_pixel = write_op_luma(&mut bytes, &op, &mut ops.iter(), &previous_pixel, true);
+        let current_pixel = write_op_luma(&mut bytes, &op, &mut ops.iter(), &previous_pixel, &PixelFormat::from(PixelLayout::RGBA));
 
         assert_eq!(bytes, expected);
         assert_eq!(current_pixel, expected_pixel);
@@ -981,7 +1837,7 @@ mod tests {
 
         let ops = vec![byte2];
 
-        let current_pixel = write_op_luma(&mut bytes, &op, &mut ops.iter(), &previous_pixel, false);
+        let current_pixel = write_op_luma(&mut bytes, &op, &mut ops.iter(), &previous_pixel, &PixelFormat::from(PixelLayout::RGB));
 
         assert_eq!(bytes, expected);
         assert_eq!(current_pixel, expected_pixel);
@@ -1004,7 +1860,7 @@ mod tests {
 
         let op = (QOI_OP_RUN << 6) + 2;
 
-        let run_len = write_op_run(&mut bytes, &op, &previous_pixel, true);
+        let run_len = write_op_run(&mut bytes, &op, &previous_pixel, &PixelFormat::from(PixelLayout::RGBA));
 
         assert_eq!(bytes, expected);
         assert_eq!(run_len, 3);
@@ -1027,7 +1883,7 @@ mod tests {
 
         let op = (QOI_OP_RUN << 6) + 2;
 
-        let run_len = write_op_run(&mut bytes, &op, &previous_pixel, false);
+        let run_len = write_op_run(&mut bytes, &op, &previous_pixel, &PixelFormat::from(PixelLayout::RGB));
 
         assert_eq!(bytes, expected);
         assert_eq!(run_len, 3);
@@ -1,260 +1,427 @@
-use std::u8;
+use core::fmt;
+#[cfg(feature = "std")]
+use std::io::ErrorKind;
+
+use crate::layout::{PixelFormat, PixelLayout};
+use crate::output::Output;
+use crate::simd;
+use crate::{Channels, Colorspace, ImgMetadata, Pixel, END_MARKER, HEADER_SIZE, QOI_OP_DIFF, QOI_OP_INDEX, QOI_OP_LUMA, QOI_OP_RGB, QOI_OP_RGBA, QOI_OP_RUN, QOI_PIXELS_MAX};
+
+/// Reasons a pixel buffer could not be encoded as a QOI image.
+#[derive(Eq, PartialEq, Debug)]
+pub enum EncodeError {
+    /// The pixel buffer length doesn't equal `width * height * channels`.
+    BufferSizeMismatch { expected: usize, actual: usize },
+    /// Width or height is zero.
+    ZeroDimensions { width: u32, height: u32 },
+    /// `width * height` is larger than [`QOI_PIXELS_MAX`].
+    TooManyPixels { width: u32, height: u32 },
+    /// A region row doesn't fit within `stride_bytes`, so rows would overlap.
+    StrideTooSmall { stride_bytes: usize, required: usize },
+    /// The output buffer can't hold the encoded image.
+    OutputTooSmall { required: usize, available: usize },
+    /// A different number of pixels than `width * height` was given to [`crate::encode_pixels`] or a [`crate::QoiEncoder`].
+    PixelCountMismatch { expected: usize, actual: usize },
+    /// The underlying writer failed.
+    #[cfg(feature = "std")]
+    Io { kind: ErrorKind },
+}
 
-use crate::{Channels, Colorspace, ImgMetadata, Operation, Pixel, QOI_OP_DIFF, QOI_OP_INDEX, QOI_OP_LUMA, QOI_OP_RGB, QOI_OP_RGBA, QOI_OP_RUN};
+impl fmt::Display for EncodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EncodeError::BufferSizeMismatch { expected, actual } => write!(f, "pixel buffer holds {actual} bytes but the metadata requires {expected}"),
+            EncodeError::ZeroDimensions { width, height } => write!(f, "image dimensions {width}x{height} must both be non-zero"),
+            EncodeError::TooManyPixels { width, height } => write!(f, "image dimensions {width}x{height} exceed the limit of {QOI_PIXELS_MAX} pixels"),
+            EncodeError::StrideTooSmall { stride_bytes, required } => write!(f, "row stride of {stride_bytes} bytes is smaller than the {required} bytes each region row needs"),
+            EncodeError::OutputTooSmall { required, available } => write!(f, "output buffer holds {available} bytes but the encoded image needs {required}"),
+            EncodeError::PixelCountMismatch { expected, actual } => write!(f, "image requires {expected} pixels but {actual} were written"),
+            #[cfg(feature = "std")]
+            EncodeError::Io { kind } => write!(f, "write error: {kind}"),
+        }
+    }
+}
 
+#[cfg(feature = "std")]
+impl std::error::Error for EncodeError {}
 
+/// Checks that `pixels` is a complete image in `layout` with the dimensions in `metadata`.
+pub(crate) fn validate(pixels: &[u8], layout: &PixelLayout, metadata: &ImgMetadata) -> Result<(), EncodeError> {
+    validate_dimensions(metadata)?;
 
-pub(crate) fn add_header(bytes: &mut Vec<u8>, metadata: &ImgMetadata) {
-    assert_eq!(bytes.len(), 0);
+    let expected = metadata.width as usize * metadata.height as usize * layout.bytes_per_pixel();
+    if pixels.len() != expected {
+        return Err(EncodeError::BufferSizeMismatch { expected, actual: pixels.len() });
+    }
 
-    let magic = vec![b'q', b'o', b'i', b'f'];
-    bytes.extend(magic);
+    Ok(())
+}
 
-    let width = metadata.width;
-    let height = metadata.height;
-    bytes.extend(width.to_be_bytes());
-    bytes.extend(height.to_be_bytes());
+/// Checks that the `metadata.width` by `metadata.height` region at `x`, `y` lies within `pixels`, whose rows are `stride_bytes` apart.
+pub(crate) fn validate_region(pixels: &[u8], layout: &PixelLayout, stride_bytes: usize, x: u32, y: u32, metadata: &ImgMetadata) -> Result<(), EncodeError> {
+    validate_dimensions(metadata)?;
 
-    match metadata.channels {
-        Channels::RGB => {bytes.push(3)}
-        Channels::RGBA => {bytes.push(4)}
+    //saturating so huge regions fail the checks below instead of overflowing
+    let row_end = (x as usize).saturating_add(metadata.width as usize).saturating_mul(layout.bytes_per_pixel());
+    if row_end > stride_bytes {
+        return Err(EncodeError::StrideTooSmall { stride_bytes, required: row_end });
     }
 
-    match metadata.colorspace {
-        Colorspace::SrgbLinearAlpha => {bytes.push(0)}
-        Colorspace::AllLinearAlpha => {bytes.push(1)}
+    let last_row = (y as usize).saturating_add(metadata.height as usize - 1);
+    let expected = last_row.saturating_mul(stride_bytes).saturating_add(row_end);
+    if pixels.len() < expected {
+        return Err(EncodeError::BufferSizeMismatch { expected, actual: pixels.len() });
     }
 
+    Ok(())
 }
 
-pub(crate) fn add_chunks(mut bytes: &mut Vec<u8>, pixels: &Vec<u8>, alpha_included: bool) -> Result<(),()>{
-    // println!("Adding chunks for: {:?}", pixels);
-    let expected_values_per_pixel = match alpha_included {
-        true => {4}
-        false => {3}
-    };
+/// Checks that the image described by `metadata` is neither empty nor larger than [`QOI_PIXELS_MAX`].
+pub(crate) fn validate_dimensions(metadata: &ImgMetadata) -> Result<(), EncodeError> {
+    let width = metadata.width;
+    let height = metadata.height;
 
-    // println!("Expected pixels: {expected_values_per_pixel}");
-    //todo - better error messaging
-    if pixels.len() % expected_values_per_pixel != 0 {return Err(())};
+    if width == 0 || height == 0 {
+        return Err(EncodeError::ZeroDimensions { width, height });
+    }
 
-    // println!("Didn't return an error");
+    let total_pixels = width as u64 * height as u64;
+    if total_pixels > QOI_PIXELS_MAX {
+        return Err(EncodeError::TooManyPixels { width, height });
+    }
 
-    let mut index: [Option<Pixel>; 64] = [None; 64];
+    Ok(())
+}
 
-    let mut previous_pixel = Pixel{
-        r: 0,
-        g: 0,
-        b: 0,
-        a: 255,
-    };
+pub(crate) fn channels_per_pixel(metadata: &ImgMetadata) -> usize {
+    match metadata.channels {
+        Channels::RGB => {3}
+        Channels::RGBA => {4}
+    }
+}
 
-    index[calculate_index(&previous_pixel)] = Some(previous_pixel.clone());
+/// Upper bound on the encoded size of an image described by `metadata`, used to size output buffers.
+pub(crate) fn max_encoded_len(metadata: &ImgMetadata) -> usize {
+    //every pixel is at worst a tag byte followed by all of its channels
+    HEADER_SIZE + metadata.width as usize * metadata.height as usize * (channels_per_pixel(metadata) + 1) + END_MARKER.len()
+}
 
-    let zero_pixel = Pixel {
-        r: 0,
-        g: 0,
-        b: 0,
-        a: 0,
-    };
+/// Writes the complete QOI file for `pixels`, stored in `format`, to `bytes`.
+pub(crate) fn encode(bytes: &mut impl Output, pixels: &[u8], format: &PixelFormat, metadata: &ImgMetadata) -> Result<(), EncodeError> {
+    validate(pixels, &format.layout, metadata)?;
 
-    index[calculate_index(&zero_pixel)] = Some(zero_pixel.clone());
+    bytes.reserve(max_encoded_len(metadata));
+    add_header(bytes, metadata);
 
-    let mut run_count: u8 = 0;
+    //validate has already checked the buffer holds whole pixels
+    add_chunks(bytes, pixels, format, metadata.channels == Channels::RGBA).unwrap();
 
-    let mut pixel_iter = pixels.iter();
+    add_end_marker(bytes);
 
-    while pixel_iter.len() >= expected_values_per_pixel {
-        // println!("Entering Loop");
-        let pixel = Pixel{
-            r: *pixel_iter.next().unwrap(),
-            g: *pixel_iter.next().unwrap(),
-            b: *pixel_iter.next().unwrap(),
-            a: match alpha_included {
-                true => {*pixel_iter.next().unwrap()}
-                false => {255}
-            },
-        };
+    Ok(())
+}
 
-        let operation = find_operation(&previous_pixel, &pixel, &index);
+/// Writes the complete QOI file for `pixels` to `bytes`, which must hold exactly `width * height` pixels.
+#[cfg(feature = "alloc")]
+pub(crate) fn encode_pixels(bytes: &mut impl Output, pixels: &[Pixel], metadata: &ImgMetadata) -> Result<(), EncodeError> {
+    validate_dimensions(metadata)?;
 
-        // println!("Got operation {:?}", operation);
+    let expected = metadata.width as usize * metadata.height as usize;
+    if pixels.len() != expected {
+        return Err(EncodeError::PixelCountMismatch { expected, actual: pixels.len() });
+    }
 
-        if operation != Operation::QoiOpRun && run_count > 0 {
-            push_run(&mut bytes, run_count);
-            run_count=0;
-        }
+    bytes.reserve(max_encoded_len(metadata));
+    add_header(bytes, metadata);
 
-        match operation {
-            Operation::QoiOpRgb => {push_rgb(&pixel, bytes)}
-            Operation::QoiOpRgba => {push_rgba(&pixel, bytes)}
-            Operation::QoiOpIndex => {push_index(&pixel, bytes)}
-            Operation::QoiOpDiff => {push_diff(&pixel, &previous_pixel, bytes)}
-            Operation::QoiOpLuma => {push_luma(&pixel, &previous_pixel, bytes)}
-            Operation::QoiOpRun => {
-                run_count += 1;
-                if run_count >= 63 {
-                    push_run(&mut bytes, 62);
-                    run_count -= 62;
-                }
-            }
+    let alpha_included = metadata.channels == Channels::RGBA;
+    let mut state = EncoderState::new();
+    for pixel in pixels {
+        let mut pixel = *pixel;
+        if !alpha_included {
+            pixel.a = 255;
         }
-
-        index[calculate_index(&pixel)] = Some(pixel.clone());
-        previous_pixel = pixel;
-
+        state.add_pixel(bytes, pixel);
     }
+    state.flush_run(bytes);
 
-    if run_count > 0 {
-        push_run(&mut bytes, run_count);
-    }
+    add_end_marker(bytes);
 
     Ok(())
 }
 
-pub(crate) fn add_end_marker(bytes: &mut Vec<u8>) {
-    for _ in 0..7 {
-        bytes.push(0);
-    }
-    bytes.push(1);
-}
+/// Writes the complete QOI file for the region of `pixels` described by [`validate_region`] to `bytes`.
+pub(crate) fn encode_region(bytes: &mut impl Output, pixels: &[u8], format: &PixelFormat, stride_bytes: usize, x: u32, y: u32, metadata: &ImgMetadata) -> Result<(), EncodeError> {
+    validate_region(pixels, &format.layout, stride_bytes, x, y, metadata)?;
 
-fn tag_byte(tag: u8, lower_bits: u8) -> u8 {
-    assert!(tag < 4);
-    assert!(lower_bits < 64);
+    bytes.reserve(max_encoded_len(metadata));
+    add_header(bytes, metadata);
 
-    (tag << 6) + lower_bits
-}
+    let row_start = x as usize * format.bytes_per_pixel();
+    let row_bytes = metadata.width as usize * format.bytes_per_pixel();
 
-//todo - move the index into its own module
-pub fn calculate_index(pixel: &Pixel) -> usize {
-    let index:u32 = (pixel.r as u32) * 3 + (pixel.g as u32) * 5 + (pixel.b as u32) * 7 + (pixel.a as u32) * 11;
-    (index % 64) as usize
+    let mut state = EncoderState::new();
+    for row in pixels[y as usize * stride_bytes..].chunks(stride_bytes).take(metadata.height as usize) {
+        state.add_pixels(bytes, &row[row_start..row_start + row_bytes], format, metadata.channels == Channels::RGBA);
+    }
+    state.flush_run(bytes);
+
+    add_end_marker(bytes);
+
+    Ok(())
 }
 
-fn create_diff(curr: &Pixel, prev: &Pixel) -> u8 {
-    let dr = u8::wrapping_sub(curr.r, prev.r);
-    let dg = u8::wrapping_sub(curr.g, prev.g);
-    let db = u8:: wrapping_sub(curr.b, prev.b);
+pub(crate) fn add_header(bytes: &mut impl Output, metadata: &ImgMetadata) {
+    bytes.extend_from_slice(b"qoif");
 
-    let dr= u8::wrapping_add(dr, 2);
-    let dg = u8::wrapping_add(dg, 2);
-    let db= u8::wrapping_add(db, 2);
+    let width = metadata.width;
+    let height = metadata.height;
+    bytes.extend_from_slice(&width.to_be_bytes());
+    bytes.extend_from_slice(&height.to_be_bytes());
 
-    assert!(dr < 4);
-    assert!(dg < 4);
-    assert!(db < 4);
+    match metadata.channels {
+        Channels::RGB => {bytes.push(3)}
+        Channels::RGBA => {bytes.push(4)}
+    }
 
-    let mut lower_bits: u8 = dr;
-    lower_bits = (lower_bits << 2) + dg;
-    lower_bits = (lower_bits << 2) + db;
+    match metadata.colorspace {
+        Colorspace::SrgbLinearAlpha => {bytes.push(0)}
+        Colorspace::AllLinearAlpha => {bytes.push(1)}
+    }
 
-    tag_byte(QOI_OP_DIFF, lower_bits)
 }
 
-fn create_diff_luma(curr: &Pixel, prev: &Pixel) -> Vec<u8> {
-    let dr = u8::wrapping_sub(curr.r, prev.r);
-    let dg = u8::wrapping_sub(curr.g, prev.g);
-    let db = u8::wrapping_sub(curr.b, prev.b);
-
-    let dr_dg = u8::wrapping_sub(dr, dg);
-    let db_dg = u8::wrapping_sub(db, dg);
+pub(crate) fn add_chunks(bytes: &mut impl Output, pixels: &[u8], format: &PixelFormat, alpha_included: bool) -> Result<(),()>{
+    let expected_values_per_pixel = format.bytes_per_pixel();
 
-    let dg = u8::wrapping_add(dg, 32);
-    let dr_dg = u8::wrapping_add(dr_dg, 8);
-    let db_dg = u8::wrapping_add(db_dg, 8);
+    //todo - better error messaging
+    if pixels.len() % expected_values_per_pixel != 0 {return Err(())};
 
-    assert!(dg < 64);
-    assert!(dr_dg < 16);
-    assert!(db_dg < 16);
+    let mut state = EncoderState::new();
+    state.add_pixels(bytes, pixels, format, alpha_included);
+    state.flush_run(bytes);
 
-    let mut bytes = Vec::new();
-    bytes.push(tag_byte(QOI_OP_LUMA, dg));
-    let mut byte2 = dr_dg;
-    byte2 = (byte2 << 4) + db_dg;
-    bytes.push(byte2);
-    bytes
+    Ok(())
 }
 
-fn find_operation(prev_pixel: &Pixel, curr_pixel: &Pixel, index: &[Option<Pixel>]) -> Operation {
-    // println!("pp: {:?} cp: {:?}", prev_pixel, curr_pixel);
-    if prev_pixel.a != curr_pixel.a { return Operation::QoiOpRgba; };
-    // println!("Not alpha diff");
-    if *prev_pixel == *curr_pixel {return Operation::QoiOpRun;}
-    if let Some(index_pixel) = index[calculate_index(curr_pixel)] {
-        if index_pixel == *curr_pixel {return Operation::QoiOpIndex;}
-    }
-
-    let dr = u8::wrapping_sub(curr_pixel.r, prev_pixel.r);
-    let dg = u8::wrapping_sub(curr_pixel.g, prev_pixel.g);
-    let db = u8::wrapping_sub(curr_pixel.b, prev_pixel.b);
+/// Longest chunk written for one pixel: a pending run followed by a QOI_OP_RGBA.
+const MAX_PIXEL_BYTES: usize = 6;
 
+/// Pixels encoded into a stack buffer before it is copied to the output, few enough to keep the buffer under 400 bytes.
+const BATCH_PIXELS: usize = 64;
 
-    let dr_2 = u8::wrapping_add(dr, 2);
-    let dg_2 = u8::wrapping_add(dg, 2);
-    let db_2 = u8::wrapping_add(db, 2);
+/// The previous pixel, color index and pending run carried from one pixel to the next.
+pub(crate) struct EncoderState {
+    //the spec starts the index zeroed, so empty slots hold the transparent black zero pixel
+    index: [Pixel; 64],
+    previous_pixel: Pixel,
+    run_count: u8,
+}
 
-    // println!("dr_2: {dr_2} dg_2: {dg_2} db_2: {db_2}");
+impl EncoderState {
+    pub(crate) fn new() -> EncoderState {
+        EncoderState {
+            index: [Pixel { r: 0, g: 0, b: 0, a: 0 }; 64],
+            //the opaque black previous pixel is not part of the index
+            previous_pixel: Pixel {
+                r: 0,
+                g: 0,
+                b: 0,
+                a: 255,
+            },
+            run_count: 0,
+        }
+    }
 
-    if dr_2 < 4 && dg_2 < 4 && db_2 < 4 {
-        return Operation::QoiOpDiff;
+    /// State for encoding pixels that continue a stream whose previous pixel and index are unknown.
+    ///
+    /// `first`, the next pixel to be added, is always written as a literal: QOI_OP_RGBA when `alpha_included`, otherwise
+    /// QOI_OP_RGB, as alpha is then 255 throughout. No index slot can match until this state has stored a pixel there,
+    /// which the decoder does at the same time, so the chunks read the same whatever came before them.
+    #[cfg(feature = "std")]
+    pub(crate) fn resynchronized(first: Pixel, alpha_included: bool) -> EncoderState {
+        let mut index = [Pixel { r: 0, g: 0, b: 0, a: 0 }; 64];
+        //the zero pixel hashes to slot 0, so it can only be matched there
+        index[0] = Pixel { r: 1, g: 0, b: 0, a: 0 };
+
+        EncoderState {
+            index,
+            //too far from `first` in every channel for a run, QOI_OP_DIFF or QOI_OP_LUMA
+            previous_pixel: Pixel {
+                r: first.r ^ 0x80,
+                g: first.g ^ 0x80,
+                b: first.b ^ 0x80,
+                a: if alpha_included {first.a ^ 0x80} else {first.a},
+            },
+            run_count: 0,
+        }
     }
 
-    let dr_dg = u8::wrapping_sub(dr, dg);
-    // println!("dr_dg: {dr_dg}");
-    let dr_dg_8 = u8::wrapping_add(dr_dg, 8);
-    // println!("dr_dg_8: {dr_dg_8}");
+    /// Adds every whole pixel in `pixels`, which are stored in `format`.
+    ///
+    /// Alpha is treated as 255 unless `alpha_included`, so RGB images stay opaque whatever the input layout.
+    pub(crate) fn add_pixels(&mut self, bytes: &mut impl Output, pixels: &[u8], format: &PixelFormat, alpha_included: bool) {
+        //the packed layouts get their own loops so reading a pixel doesn't branch on the format
+        match (format.layout, format.premultiplied, alpha_included) {
+            (PixelLayout::RGBA, false, true) => {
+                self.add_pixels_with(bytes, pixels, 4, |values| Pixel { r: values[0], g: values[1], b: values[2], a: values[3] })
+            }
+            (PixelLayout::RGBA, false, false) => {
+                self.add_pixels_with(bytes, pixels, 4, |values| Pixel { r: values[0], g: values[1], b: values[2], a: 255 })
+            }
+            (PixelLayout::RGB, false, _) => {
+                self.add_pixels_with(bytes, pixels, 3, |values| Pixel { r: values[0], g: values[1], b: values[2], a: 255 })
+            }
+            _ => {
+                self.add_pixels_with(bytes, pixels, format.bytes_per_pixel(), |values| {
+                    let mut pixel = format.read_pixel(values);
+                    if !alpha_included {
+                        pixel.a = 255;
+                    }
+                    pixel
+                })
+            }
+        }
+    }
 
-    let db_dg = u8::wrapping_sub(db, dg);
-    // println!("db_dg: {db_dg}");
-    let db_dg_8 = u8::wrapping_add(db_dg, 8);
-    // println!("db_dg_8: {db_dg_8}");
+    /// Adds every whole pixel in `pixels`, reading each `bytes_per_pixel` bytes with `read_pixel`.
+    #[inline(always)]
+    fn add_pixels_with(&mut self, bytes: &mut impl Output, pixels: &[u8], bytes_per_pixel: usize, read_pixel: impl Fn(&[u8]) -> Pixel) {
+        let mut batch = [0; BATCH_PIXELS * MAX_PIXEL_BYTES];
+
+        for pixels in pixels.chunks(BATCH_PIXELS * bytes_per_pixel) {
+            let mut len = 0;
+            let mut offset = 0;
+            while offset + bytes_per_pixel <= pixels.len() {
+                let values = &pixels[offset..offset + bytes_per_pixel];
+                len = self.encode_pixel(&mut batch, len, read_pixel(values));
+                offset += bytes_per_pixel;
+
+                //once a run has started, the rest of it is found many pixels at a time
+                if self.run_count > 0 {
+                    let repeats = simd::count_repeats(&pixels[offset - bytes_per_pixel..], bytes_per_pixel);
+                    len = self.extend_run(&mut batch, len, repeats);
+                    offset += repeats * bytes_per_pixel;
+                }
+            }
+            bytes.extend_from_slice(&batch[..len]);
+        }
+    }
 
-    let dg_32 = u8::wrapping_add(dg, 32);
-    // println!("dg_32: {dg_32}");
+    /// Adds `repeats` more pixels to the pending run, writing a full run chunk into `out` at `at` for every 62.
+    ///
+    /// Returns the new end of the written bytes, the same as adding each pixel with [`Self::encode_pixel`].
+    fn extend_run(&mut self, out: &mut [u8], mut at: usize, repeats: usize) -> usize {
+        let run = self.run_count as usize + repeats;
+        for _ in 0..run / 62 {
+            out[at] = tag_byte(QOI_OP_RUN, 61);
+            at += 1;
+        }
+        self.run_count = (run % 62) as u8;
+        at
+    }
+
+    #[cfg(feature = "alloc")]
+    fn add_pixel(&mut self, bytes: &mut impl Output, pixel: Pixel) {
+        let mut chunk = [0; MAX_PIXEL_BYTES];
+        let len = self.encode_pixel(&mut chunk, 0, pixel);
+        bytes.extend_from_slice(&chunk[..len]);
+    }
+
+    /// Writes the chunks for `pixel` into `out` at `at` and returns the new end of the written bytes.
+    ///
+    /// `out` must have room for [`MAX_PIXEL_BYTES`] more bytes, so there is only one bounds check per pixel. Each
+    /// difference is computed once. An alpha change always takes QOI_OP_RGBA, even when the pixel is in the index.
+    #[inline(always)]
+    fn encode_pixel(&mut self, out: &mut [u8], at: usize, pixel: Pixel) -> usize {
+        let chunk = &mut out[at..at + MAX_PIXEL_BYTES];
+        let mut len = 0;
+
+        if pixel == self.previous_pixel {
+            self.run_count += 1;
+            if self.run_count == 62 {
+                chunk[0] = tag_byte(QOI_OP_RUN, 61);
+                self.run_count = 0;
+                len = 1;
+            }
+            return at + len;
+        }
 
-    if  dg_32 < 64 && dr_dg_8 < 16 && db_dg_8 < 16{
-        return Operation::QoiOpLuma;
-    }
+        if self.run_count > 0 {
+            chunk[0] = tag_byte(QOI_OP_RUN, self.run_count - 1);
+            self.run_count = 0;
+            len = 1;
+        }
 
-    Operation::QoiOpRgb
-}
+        let prev = self.previous_pixel;
+        let hash = calculate_index(&pixel);
+
+        if pixel.a != prev.a {
+            chunk[len..len + 5].copy_from_slice(&[QOI_OP_RGBA, pixel.r, pixel.g, pixel.b, pixel.a]);
+            len += 5;
+        } else if self.index[hash] == pixel {
+            chunk[len] = tag_byte(QOI_OP_INDEX, hash as u8);
+            len += 1;
+        } else {
+            let dr = pixel.r.wrapping_sub(prev.r);
+            let dg = pixel.g.wrapping_sub(prev.g);
+            let db = pixel.b.wrapping_sub(prev.b);
+
+            //the differences with their bias added, so each range check is a single comparison
+            let dr_2 = dr.wrapping_add(2);
+            let dg_2 = dg.wrapping_add(2);
+            let db_2 = db.wrapping_add(2);
+            let dg_32 = dg.wrapping_add(32);
+            let dr_dg_8 = dr.wrapping_sub(dg).wrapping_add(8);
+            let db_dg_8 = db.wrapping_sub(dg).wrapping_add(8);
+
+            if dr_2 < 4 && dg_2 < 4 && db_2 < 4 {
+                chunk[len] = tag_byte(QOI_OP_DIFF, dr_2 << 4 | dg_2 << 2 | db_2);
+                len += 1;
+            } else if dg_32 < 64 && dr_dg_8 < 16 && db_dg_8 < 16 {
+                chunk[len..len + 2].copy_from_slice(&[tag_byte(QOI_OP_LUMA, dg_32), dr_dg_8 << 4 | db_dg_8]);
+                len += 2;
+            } else {
+                chunk[len..len + 4].copy_from_slice(&[QOI_OP_RGB, pixel.r, pixel.g, pixel.b]);
+                len += 4;
+            }
+        }
 
-fn push_run(bytes: &mut Vec<u8>, run_length: u8) {
-    assert!(run_length > 0 && run_length < 63);
-    bytes.push(tag_byte(QOI_OP_RUN, run_length - 1));
-}
+        self.index[hash] = pixel;
+        self.previous_pixel = pixel;
+        at + len
+    }
 
-fn push_rgb(pixel: &Pixel, bytes: &mut Vec<u8>) {
-    bytes.push(QOI_OP_RGB);
-    bytes.push(pixel.r);
-    bytes.push(pixel.g);
-    bytes.push(pixel.b);
+    /// Writes out the pending run, if there is one.
+    pub(crate) fn flush_run(&mut self, bytes: &mut impl Output) {
+        if self.run_count > 0 {
+            bytes.push(tag_byte(QOI_OP_RUN, self.run_count - 1));
+            self.run_count = 0;
+        }
+    }
 }
 
-fn push_rgba(pixel: &Pixel, bytes: &mut Vec<u8>) {
-    bytes.push(QOI_OP_RGBA);
-    bytes.push(pixel.r);
-    bytes.push(pixel.g);
-    bytes.push(pixel.b);
-    bytes.push(pixel.a);
+pub(crate) fn add_end_marker(bytes: &mut impl Output) {
+    bytes.extend_from_slice(&END_MARKER);
 }
 
-fn push_index(pixel: &Pixel, bytes: &mut Vec<u8>) {
-    bytes.push(tag_byte(QOI_OP_INDEX, calculate_index(&pixel).try_into().unwrap()));
-}
+fn tag_byte(tag: u8, lower_bits: u8) -> u8 {
+    debug_assert!(tag < 4);
+    debug_assert!(lower_bits < 64);
 
-fn push_diff(curr: &Pixel, prev: &Pixel, bytes: &mut Vec<u8>) {
-    bytes.push(create_diff(&curr, &prev));
+    (tag << 6) | lower_bits
 }
 
-fn push_luma(curr: &Pixel, prev: &Pixel, bytes: &mut Vec<u8>) {
-    bytes.extend(create_diff_luma(&curr, &prev));
+//todo - move the index into its own module
+pub fn calculate_index(pixel: &Pixel) -> usize {
+    let index:u32 = (pixel.r as u32) * 3 + (pixel.g as u32) * 5 + (pixel.b as u32) * 7 + (pixel.a as u32) * 11;
+    (index % 64) as usize
 }
 
-
-#[cfg(test)]
+#[cfg(all(test, feature = "alloc"))]
+#[allow(clippy::identity_op, clippy::useless_vec)]
 mod tests {
+    use crate::test_util::metadata;
     use crate::{Channels, Colorspace};
 
     use super::*;
@@ -266,6 +433,73 @@ mod tests {
         assert_eq!(vec![0,0,0,0,0,0,0,1], vec);
     }
 
+    #[test]
+    fn validate_success() {
+        assert_eq!(validate(&[0; 24], &PixelLayout::RGBA, &metadata(3, 2, Channels::RGBA)), Ok(()));
+        assert_eq!(validate(&[0; 18], &PixelLayout::RGB, &metadata(3, 2, Channels::RGB)), Ok(()));
+    }
+
+    #[test]
+    fn validate_buffer_size() {
+        assert_eq!(validate(&[0; 18], &PixelLayout::RGBA, &metadata(3, 2, Channels::RGBA)), Err(EncodeError::BufferSizeMismatch { expected: 24, actual: 18 }));
+        assert_eq!(validate(&[0; 21], &PixelLayout::RGB, &metadata(3, 2, Channels::RGB)), Err(EncodeError::BufferSizeMismatch { expected: 18, actual: 21 }));
+    }
+
+    #[test]
+    fn validate_zero_dimensions() {
+        assert_eq!(validate(&[], &PixelLayout::RGB, &metadata(0, 2, Channels::RGB)), Err(EncodeError::ZeroDimensions { width: 0, height: 2 }));
+        assert_eq!(validate(&[], &PixelLayout::RGB, &metadata(2, 0, Channels::RGB)), Err(EncodeError::ZeroDimensions { width: 2, height: 0 }));
+    }
+
+    #[test]
+    fn validate_too_many_pixels() {
+        assert_eq!(validate(&[], &PixelLayout::RGB, &metadata(20_001, 20_000, Channels::RGB)), Err(EncodeError::TooManyPixels { width: 20_001, height: 20_000 }));
+        assert_eq!(validate(&[], &PixelLayout::RGBA, &metadata(u32::MAX, u32::MAX, Channels::RGBA)), Err(EncodeError::TooManyPixels { width: u32::MAX, height: u32::MAX }));
+    }
+
+    #[test]
+    fn validate_region_success() {
+        //3x2 region at 1,1 inside a 5 pixel wide RGB image with 2 bytes of row padding
+        assert_eq!(validate_region(&[0; 2 * 17 + 12], &PixelLayout::RGB, 17, 1, 1, &metadata(3, 2, Channels::RGB)), Ok(()));
+        assert_eq!(validate_region(&[0; 16], &PixelLayout::RGBA, 16, 0, 0, &metadata(4, 1, Channels::RGBA)), Ok(()));
+    }
+
+    #[test]
+    fn validate_region_stride_too_small() {
+        assert_eq!(validate_region(&[0; 64], &PixelLayout::RGBA, 16, 1, 0, &metadata(4, 1, Channels::RGBA)), Err(EncodeError::StrideTooSmall { stride_bytes: 16, required: 20 }));
+        assert!(validate_region(&[0; 64], &PixelLayout::RGBA, 16, u32::MAX, 0, &metadata(4, 1, Channels::RGBA)).is_err());
+    }
+
+    #[test]
+    fn validate_region_buffer_too_small() {
+        assert_eq!(validate_region(&[0; 2 * 17 + 11], &PixelLayout::RGB, 17, 1, 1, &metadata(3, 2, Channels::RGB)), Err(EncodeError::BufferSizeMismatch { expected: 2 * 17 + 12, actual: 2 * 17 + 11 }));
+        assert_eq!(validate_region(&[0; 64], &PixelLayout::RGB, 16, 0, 3, &metadata(1, 2, Channels::RGB)), Err(EncodeError::BufferSizeMismatch { expected: 67, actual: 64 }));
+    }
+
+    #[test]
+    fn encode_region_matches_packed() {
+        //a 4x3 RGB frame with 2 bytes of padding per row, encoding the 2x2 region at 1,1
+        let stride = 14;
+        let mut frame = vec![0; stride * 3];
+        let mut packed = Vec::new();
+        for y in 0..3 {
+            for x in 0..4 {
+                let pixel = [x as u8 * 60, y as u8 * 80, 7];
+                frame[y * stride + x * 3..y * stride + x * 3 + 3].copy_from_slice(&pixel);
+                if (1..3).contains(&x) && (1..3).contains(&y) {
+                    packed.extend(pixel);
+                }
+            }
+        }
+        let metadata = metadata(2, 2, Channels::RGB);
+
+        let mut region = Vec::new();
+        encode_region(&mut region, &frame, &PixelFormat::from(PixelLayout::RGB), stride, 1, 1, &metadata).unwrap();
+        let mut expected = Vec::new();
+        encode(&mut expected, &packed, &PixelFormat::from(PixelLayout::RGB), &metadata).unwrap();
+        assert_eq!(region, expected);
+    }
+
     #[test]
     fn header_rgb_srgb() {
         let metadata = ImgMetadata {
@@ -301,7 +535,7 @@ mod tests {
     fn chunk_rgb() {
         let pixel = vec![50, 50, 50];
         let mut bytes = Vec::new();
-        add_chunks(&mut bytes, &pixel, false).unwrap();
+        add_chunks(&mut bytes, &pixel, &PixelFormat::from(PixelLayout::RGB), false).unwrap();
         assert_eq!(bytes, vec![QOI_OP_RGB, 50, 50, 50]);
     }
 
@@ -309,7 +543,7 @@ mod tests {
     fn chunk_rgba() {
         let pixel = vec![50, 50, 50, 50];
         let mut bytes = Vec::new();
-        add_chunks(&mut bytes, &pixel, true).unwrap();
+        add_chunks(&mut bytes, &pixel, &PixelFormat::from(PixelLayout::RGBA), true).unwrap();
         assert_eq!(bytes, vec![QOI_OP_RGBA, 50, 50, 50, 50]);
     }
 
@@ -317,7 +551,7 @@ mod tests {
     fn chunk_rgba_unchanged_alpha() {
         let pixel = vec![50, 50, 50, 255];
         let mut bytes = Vec::new();
-        add_chunks(&mut bytes, &pixel, true).unwrap();
+        add_chunks(&mut bytes, &pixel, &PixelFormat::from(PixelLayout::RGBA), true).unwrap();
         assert_eq!(bytes, vec![QOI_OP_RGB, 50, 50, 50]);
     }
 
@@ -362,7 +596,7 @@ mod tests {
         pixels.extend(&pixel_1);
 
         let mut bytes = Vec::new();
-        add_chunks(&mut bytes, &pixels, false).unwrap();
+        add_chunks(&mut bytes, &pixels, &PixelFormat::from(PixelLayout::RGB), false).unwrap();
 
         let op1 = vec![QOI_OP_RGB, 50, 50, 50];
         let op2 = vec![QOI_OP_RGB, 255, 255, 255];
@@ -379,6 +613,45 @@ mod tests {
         assert_eq!(bytes, expected);
     }
 
+    #[test]
+    fn index_starts_without_black() {
+        //opaque black is only the initial previous pixel, so it must not be found in the index
+        let pixels = vec![100, 100, 100, 255,
+                          0, 0, 0, 255];
+
+        let mut bytes = Vec::new();
+        add_chunks(&mut bytes, &pixels, &PixelFormat::from(PixelLayout::RGBA), true).unwrap();
+
+        assert_eq!(bytes, vec![QOI_OP_RGB, 100, 100, 100,
+                               QOI_OP_RGB, 0, 0, 0]);
+    }
+
+    #[test]
+    fn index_skips_initial_run() {
+        let pixels = vec![0, 0, 0, 255,
+                          100, 100, 100, 255,
+                          0, 0, 0, 255];
+
+        let mut bytes = Vec::new();
+        add_chunks(&mut bytes, &pixels, &PixelFormat::from(PixelLayout::RGBA), true).unwrap();
+
+        assert_eq!(bytes, vec![tag_byte(QOI_OP_RUN, 0),
+                               QOI_OP_RGB, 100, 100, 100,
+                               QOI_OP_RGB, 0, 0, 0]);
+    }
+
+    #[test]
+    fn index_starts_with_zero_pixel() {
+        let pixels = vec![100, 100, 100, 0,
+                          0, 0, 0, 0];
+
+        let mut bytes = Vec::new();
+        add_chunks(&mut bytes, &pixels, &PixelFormat::from(PixelLayout::RGBA), true).unwrap();
+
+        assert_eq!(bytes, vec![QOI_OP_RGBA, 100, 100, 100, 0,
+                               tag_byte(QOI_OP_INDEX, 0)]);
+    }
+
     #[test]
     fn diff_fn() {
         let prev = Pixel {
@@ -393,7 +666,7 @@ mod tests {
             b: 88,
             a: 0,
         };
-        assert_eq!(create_diff(&curr, &prev), tag_byte(QOI_OP_DIFF, 0b00000110));
+        assert_eq!(encode_after(prev, &[], curr), vec![tag_byte(QOI_OP_DIFF, 0b00000110)]);
     }
 
     //todo - test to make sure diff and luma properly handle wraparounds
@@ -417,10 +690,9 @@ mod tests {
 
         let mut bytes = Vec::new();
 
-        add_chunks(&mut bytes, &pixels, false).unwrap();
+        add_chunks(&mut bytes, &pixels, &PixelFormat::from(PixelLayout::RGB), false).unwrap();
 
-        let mut op = vec![QOI_OP_RGB, 50, 50, 50];
-        op.push(create_diff(&pixel_2, &pixel_1));
+        let op = vec![QOI_OP_RGB, 50, 50, 50, tag_byte(QOI_OP_DIFF, 0b00110001)];
 
         assert_eq!(bytes, op);
 
@@ -440,11 +712,11 @@ mod tests {
             b: 72,
             a: 0,
         };
-        let op = create_diff_luma(&p2, &p1);
+        let op = encode_after(p1, &[], p2);
 
         let header = tag_byte(QOI_OP_LUMA, 62);
         let byte2 = ((0b1111 << 4) + 0b0000) as u8;
-        assert_eq!(op, vec![header, byte2]);
+        assert_eq!(op, [header, byte2]);
     }
 
     #[test]
@@ -467,11 +739,9 @@ mod tests {
 
         let mut bytes = Vec::new();
 
-        add_chunks(&mut bytes, &pixels, false).unwrap();
+        add_chunks(&mut bytes, &pixels, &PixelFormat::from(PixelLayout::RGB), false).unwrap();
 
-        let mut expected = vec![QOI_OP_RGB, 50, 50, 50];
-        let diff_luma = create_diff_luma(&pixel_2, &pixel_1);
-        expected.extend(diff_luma);
+        let expected = vec![QOI_OP_RGB, 50, 50, 50, tag_byte(QOI_OP_LUMA, 52), (11 << 4) + 3];
 
         assert_eq!(bytes, expected);
 
@@ -493,7 +763,7 @@ mod tests {
         pixels.extend(&px_50);
 
         let mut bytes = Vec::new();
-        add_chunks(&mut bytes, &pixels, false).unwrap();
+        add_chunks(&mut bytes, &pixels, &PixelFormat::from(PixelLayout::RGB), false).unwrap();
 
         //QOI_OP_RUN lower bits have a bias of -1, so the lower bits are 1 lower than the run
         let mut expected = vec![tag_byte(QOI_OP_RUN, 2)];
@@ -529,7 +799,7 @@ mod tests {
         }
 
         let mut bytes = Vec::new();
-        add_chunks(&mut bytes, &pixels, false).unwrap();
+        add_chunks(&mut bytes, &pixels, &PixelFormat::from(PixelLayout::RGB), false).unwrap();
 
         let mut expected = vec![tag_byte(QOI_OP_RUN, 61)];
         expected.push(tag_byte(QOI_OP_RUN, 0));
@@ -540,283 +810,178 @@ mod tests {
 
 
     #[test]
-    fn find_operation_rgb() {
-        let pp = Pixel {
-            r: 10,
-            g: 20,
-            b: 30,
-            a: 40,
-        };
-        let cp = Pixel {
-            r: 200,
-            g: 100,
-            b: 50,
-            a: 40,
-        };
-        let index: [Option<Pixel>; 64] = [None; 64];
+    fn encode_rgb() {
+        let pp = Pixel { r: 10, g: 20, b: 30, a: 40 };
+        let cp = Pixel { r: 200, g: 100, b: 50, a: 40 };
 
-        assert_eq!(find_operation(&pp, &cp, &index), Operation::QoiOpRgb);
+        assert_eq!(encode_after(pp, &[], cp), vec![QOI_OP_RGB, 200, 100, 50]);
     }
 
     #[test]
-    fn find_operation_rgba() {
-        let pp = Pixel {
-            r: 10,
-            g: 20,
-            b: 30,
-            a: 40,
-        };
-        let cp = Pixel {
-            r: 200,
-            g: 100,
-            b: 50,
-            a: 41,
-        };
-        let index: [Option<Pixel>; 64] = [None; 64];
+    fn encode_rgba() {
+        let pp = Pixel { r: 10, g: 20, b: 30, a: 40 };
+        let cp = Pixel { r: 200, g: 100, b: 50, a: 41 };
 
-        assert_eq!(find_operation(&pp, &cp, &index), Operation::QoiOpRgba);
+        assert_eq!(encode_after(pp, &[], cp), vec![QOI_OP_RGBA, 200, 100, 50, 41]);
     }
 
     #[test]
-    fn find_operation_index() {
-        let ip = Pixel {
-            r: 10,
-            g: 20,
-            b: 30,
-            a: 40,
-        };
-        let pp = Pixel {
-            r: 100,
-            g: 20,
-            b: 30,
-            a: 40,
-        };
-        let cp = Pixel {
-            r: 10,
-            g: 20,
-            b: 30,
-            a: 40,
-        };
-        let mut index: [Option<Pixel>; 64] = [None; 64];
-        index[calculate_index(&ip)] = Some(ip.clone());
+    fn encode_rgba_when_indexed() {
+        //an alpha change is always written in full, even when the pixel is in the index
+        let pp = Pixel { r: 10, g: 20, b: 30, a: 40 };
+        let cp = Pixel { r: 10, g: 20, b: 30, a: 41 };
 
-        assert_eq!(find_operation(&pp, &cp, &index), Operation::QoiOpIndex);
+        assert_eq!(encode_after(pp, &[cp], cp), vec![QOI_OP_RGBA, 10, 20, 30, 41]);
     }
 
     #[test]
-    fn find_operation_diff() {
-        let pp = Pixel {
-            r: 10,
-            g: 20,
-            b: 30,
-            a: 40,
-        };
-        let cp = Pixel {
-            r: 11,
-            g: 20,
-            b: 28,
-            a: 40,
-        };
-        let index: [Option<Pixel>; 64] = [None; 64];
+    fn encode_index() {
+        let ip = Pixel { r: 10, g: 20, b: 30, a: 40 };
+        let pp = Pixel { r: 100, g: 20, b: 30, a: 40 };
 
-        assert_eq!(find_operation(&pp, &cp, &index), Operation::QoiOpDiff);
+        assert_eq!(encode_after(pp, &[ip], ip), vec![tag_byte(QOI_OP_INDEX, calculate_index(&ip) as u8)]);
     }
 
     #[test]
-    fn find_operation_diff_wraparound() {
-        let pp = Pixel {
-            r: 0,
-            g: 255,
-            b: 0,
-            a: 40,
-        };
-        let cp = Pixel {
-            r: 255,
-            g: 0,
-            b: 254,
-            a: 40,
-        };
-        let index: [Option<Pixel>; 64] = [None; 64];
+    fn encode_diff() {
+        let pp = Pixel { r: 10, g: 20, b: 30, a: 40 };
+        let cp = Pixel { r: 11, g: 20, b: 28, a: 40 };
 
-        assert_eq!(find_operation(&pp, &cp, &index), Operation::QoiOpDiff);
+        assert_eq!(encode_after(pp, &[], cp), vec![tag_byte(QOI_OP_DIFF, 0b00111000)]);
     }
 
     #[test]
-    fn find_operation_luma() {
-        let pp = Pixel {
-            r: 10,
-            g: 20,
-            b: 30,
-            a: 40,
-        };
-        let cp = Pixel {
-            r: 37,
-            g: 40,
-            b: 42,
-            a: 40,
-        };
-        let index: [Option<Pixel>; 64] = [None; 64];
+    fn encode_diff_wraparound() {
+        let pp = Pixel { r: 0, g: 255, b: 0, a: 40 };
+        let cp = Pixel { r: 255, g: 0, b: 254, a: 40 };
 
-        assert_eq!(find_operation(&pp, &cp, &index), Operation::QoiOpLuma);
+        assert_eq!(encode_after(pp, &[], cp), vec![tag_byte(QOI_OP_DIFF, 0b00011100)]);
     }
 
     #[test]
-    fn find_operation_luma_wraparound() {
-        let pp = Pixel {
-            r: 0,
-            g: 255,
-            b: 0,
-            a: 40,
-        };
-        let cp = Pixel {
-            r: 250,
-            g: 1,
-            b: 2,
-            a: 40,
-        };
-        let index: [Option<Pixel>; 64] = [None; 64];
+    fn encode_luma() {
+        let pp = Pixel { r: 10, g: 20, b: 30, a: 40 };
+        let cp = Pixel { r: 37, g: 40, b: 42, a: 40 };
 
-        assert_eq!(find_operation(&pp, &cp, &index), Operation::QoiOpLuma);
+        //dg = 20, dr - dg = 7, db - dg = -8
+        assert_eq!(encode_after(pp, &[], cp), vec![tag_byte(QOI_OP_LUMA, 52), (15 << 4) + 0]);
     }
 
     #[test]
-    fn find_operation_run() {
-        let pp = Pixel {
-            r: 10,
-            g: 20,
-            b: 30,
-            a: 40,
-        };
-        let cp = Pixel {
-            r: 10,
-            g: 20,
-            b: 30,
-            a: 40,
-        };
-        let mut index: [Option<Pixel>; 64] = [None; 64];
-        index[calculate_index(&pp)] = Some(pp.clone());
+    fn encode_luma_wraparound() {
+        let pp = Pixel { r: 0, g: 255, b: 0, a: 40 };
+        let cp = Pixel { r: 250, g: 1, b: 2, a: 40 };
 
-        assert_eq!(find_operation(&pp, &cp, &index), Operation::QoiOpRun);
+        //dg = 2, dr - dg = -8, db - dg = 0
+        assert_eq!(encode_after(pp, &[], cp), vec![tag_byte(QOI_OP_LUMA, 34), (0 << 4) + 8]);
     }
 
     #[test]
-    fn push_run_test() {
-        let mut bytes = Vec::new();
-
-        push_run(&mut bytes, 10);
-
-        let expected = vec![tag_byte(QOI_OP_RUN, 9)];
+    fn encode_run() {
+        let pp = Pixel { r: 10, g: 20, b: 30, a: 40 };
+        let mut state = EncoderState::new();
+        state.previous_pixel = pp;
 
-        assert_eq!(&bytes, &expected);
-    }
-
-    #[test]
-    #[should_panic]
-    fn push_run_63() {
         let mut bytes = Vec::new();
+        for _ in 0..10 {
+            state.add_pixel(&mut bytes, pp);
+        }
+        assert_eq!(bytes, vec![]);
 
-        push_run(&mut bytes, 63);
+        state.flush_run(&mut bytes);
+        assert_eq!(bytes, vec![tag_byte(QOI_OP_RUN, 9)]);
     }
 
     #[test]
-    fn push_rgb_test() {
+    fn encode_run_62_written_at_once() {
+        let mut state = EncoderState::new();
         let mut bytes = Vec::new();
+        for _ in 0..62 {
+            state.add_pixel(&mut bytes, Pixel { r: 0, g: 0, b: 0, a: 255 });
+        }
+        assert_eq!(bytes, vec![tag_byte(QOI_OP_RUN, 61)]);
 
-        let pixel = Pixel {
-            r: 77,
-            g: 82,
-            b: 51,
-            a: 2,
-        };
-
-        push_rgb(&pixel, &mut bytes);
-
-        let expected = vec![QOI_OP_RGB, 77, 82, 51];
-
-        assert_eq!(&bytes, &expected);
+        state.flush_run(&mut bytes);
+        assert_eq!(bytes, vec![tag_byte(QOI_OP_RUN, 61)]);
     }
 
     #[test]
-    fn push_rgba_test() {
-        let mut bytes = Vec::new();
-
-        let pixel = Pixel {
-            r: 77,
-            g: 82,
-            b: 51,
-            a: 2,
-        };
+    fn encode_run_then_pixel() {
+        let pp = Pixel { r: 77, g: 82, b: 51, a: 2 };
+        let mut state = EncoderState::new();
+        state.previous_pixel = pp;
 
-        push_rgba(&pixel, &mut bytes);
-
-        let expected = vec![QOI_OP_RGBA, 77, 82, 51, 2];
+        let mut bytes = Vec::new();
+        state.add_pixel(&mut bytes, pp);
+        state.add_pixel(&mut bytes, Pixel { r: 78, g: 81, b: 51, a: 2 });
 
-        assert_eq!(&bytes, &expected);
+        assert_eq!(bytes, vec![tag_byte(QOI_OP_RUN, 0), tag_byte(QOI_OP_DIFF, 0b00110110)]);
     }
 
     #[test]
-    fn push_index_test() {
-        let mut bytes = Vec::new();
+    fn batches_match_single_pixels() {
+        //more pixels than fit in one batch, with a run spanning the batch boundary
+        let mut pixels = Vec::new();
+        for i in 0..BATCH_PIXELS as u32 * 2 + 7 {
+            let value = if (BATCH_PIXELS as u32 - 30..BATCH_PIXELS as u32 + 70).contains(&i) {9} else {(i * 7 % 251) as u8};
+            pixels.extend([value, value / 2, 255 - value, (i % 3) as u8 * 100]);
+        }
 
-        let pixel = Pixel {
-            r: 77,
-            g: 82,
-            b: 51,
-            a: 2,
-        };
-        push_index(&pixel, &mut bytes);
+        let mut batched = Vec::new();
+        let mut state = EncoderState::new();
+        state.add_pixels(&mut batched, &pixels, &PixelFormat::from(PixelLayout::RGBA), true);
+        state.flush_run(&mut batched);
 
-        let expected = vec![tag_byte(QOI_OP_INDEX, calculate_index(&pixel) as u8)];
+        let mut single = Vec::new();
+        let mut state = EncoderState::new();
+        for values in pixels.chunks_exact(4) {
+            state.add_pixel(&mut single, PixelLayout::RGBA.read_pixel(values));
+        }
+        state.flush_run(&mut single);
 
-        assert_eq!(&bytes, &expected);
+        assert_eq!(batched, single);
     }
 
+    #[cfg(feature = "std")]
     #[test]
-    fn push_diff_test() {
-        let mut bytes = Vec::new();
-
-        let prev = Pixel {
-            r: 77,
-            g: 82,
-            b: 51,
-            a: 2,
-        };
-        let curr = Pixel {
-            r: 78,
-            g: 81,
-            b: 51,
-            a: 2,
-        };
-
-        push_diff(&curr, &prev, &mut bytes);
-
-        let expected = vec![create_diff(&curr, &prev)];
-
-        assert_eq!(&bytes, &expected);
+    fn resynchronized_starts_with_literal() {
+        for first in [Pixel::new(0, 0, 0, 0), Pixel::new(0, 0, 0, 255), Pixel::new(200, 10, 128, 7)] {
+            let mut bytes = Vec::new();
+            let mut state = EncoderState::resynchronized(first, true);
+            state.add_pixel(&mut bytes, first);
+            assert_eq!(bytes, [QOI_OP_RGBA, first.r, first.g, first.b, first.a]);
+
+            let first = Pixel { a: 255, ..first };
+            let mut bytes = Vec::new();
+            let mut state = EncoderState::resynchronized(first, false);
+            state.add_pixel(&mut bytes, first);
+            assert_eq!(bytes, [QOI_OP_RGB, first.r, first.g, first.b]);
+        }
     }
 
+    #[cfg(feature = "std")]
     #[test]
-    fn push_luma_test() {
+    fn resynchronized_index_starts_empty() {
+        //a fresh index would find the zero pixel in slot 0
         let mut bytes = Vec::new();
+        let mut state = EncoderState::resynchronized(Pixel::new(5, 5, 5, 0), true);
+        state.add_pixel(&mut bytes, Pixel::new(5, 5, 5, 0));
+        state.add_pixel(&mut bytes, Pixel::new(0, 0, 0, 0));
+        state.add_pixel(&mut bytes, Pixel::new(5, 5, 5, 0));
 
-        let prev = Pixel {
-            r: 88,
-            g: 92,
-            b: 60,
-            a: 2,
-        };
-        let curr = Pixel {
-            r: 77,
-            g: 82,
-            b: 51,
-            a: 2,
-        };
-
-        push_luma(&curr, &prev, &mut bytes);
+        assert_eq!(bytes[5..], [QOI_OP_LUMA << 6 | 27, 0x88, QOI_OP_INDEX << 6 | calculate_index(&Pixel::new(5, 5, 5, 0)) as u8]);
+    }
 
-        let expected = create_diff_luma(&curr, &prev);
+    /// Encodes `pixel` right after `previous`, with `indexed` already in the index, and returns the bytes written.
+    fn encode_after(previous: Pixel, indexed: &[Pixel], pixel: Pixel) -> Vec<u8> {
+        let mut state = EncoderState::new();
+        state.previous_pixel = previous;
+        for indexed in indexed {
+            state.index[calculate_index(indexed)] = *indexed;
+        }
 
-        assert_eq!(&bytes, &expected);
+        let mut bytes = Vec::new();
+        state.add_pixel(&mut bytes, pixel);
+        bytes
     }
-
-}
\ No newline at end of file
+}
@@ -0,0 +1,209 @@
+use std::io::{self, Read, Write};
+
+use image::error::{DecodingError, EncodingError};
+use image::{ColorType, ImageDecoder, ImageEncoder, ImageError, ImageFormat, ImageResult};
+
+use crate::{Channels, Colorspace, ImgMetadata, Pixel, PixelLayout, QoiDecoder};
+
+/// Lets [`QoiDecoder`] be used with [`image::DynamicImage::from_decoder`].
+impl<'a, R: Read + 'a> ImageDecoder<'a> for QoiDecoder<R> {
+    type Reader = QoiDecoder<R>;
+
+    fn dimensions(&self) -> (u32, u32) {
+        (self.metadata().width, self.metadata().height)
+    }
+
+    fn color_type(&self) -> ColorType {
+        match self.metadata().channels {
+            Channels::RGB => {ColorType::Rgb8}
+            Channels::RGBA => {ColorType::Rgba8}
+        }
+    }
+
+    fn into_reader(self) -> ImageResult<Self::Reader> {
+        Ok(self)
+    }
+
+    fn read_image(mut self, buf: &mut [u8]) -> ImageResult<()> {
+        assert_eq!(buf.len() as u64, self.total_bytes());
+
+        //read_pixels only stops short of filling buf at the end of the image, so one call decodes all of it
+        self.read_pixels(buf)?;
+        Ok(())
+    }
+}
+
+/// Reads decoded pixels, so a [`QoiDecoder`] can be used wherever a reader of raw pixel bytes is expected.
+impl<R: Read> Read for QoiDecoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.read_pixels(buf).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+    }
+}
+
+/// Encodes images with jaqoi through [`image::DynamicImage::write_with_encoder`].
+///
+/// Only 8 bit RGB, RGBA, gray and gray with alpha images are supported. Gray images are stored as RGB or RGBA.
+pub struct QoiImageEncoder<W: Write> {
+    writer: W,
+    colorspace: Colorspace,
+}
+
+impl<W: Write> QoiImageEncoder<W> {
+    /// Creates an encoder that marks its output as sRGB with linear alpha.
+    pub fn new(writer: W) -> QoiImageEncoder<W> {
+        QoiImageEncoder {
+            writer,
+            colorspace: Colorspace::SrgbLinearAlpha,
+        }
+    }
+
+    /// Sets the colorspace written to the header. It doesn't change the pixel values.
+    pub fn with_colorspace(mut self, colorspace: Colorspace) -> QoiImageEncoder<W> {
+        self.colorspace = colorspace;
+        self
+    }
+}
+
+impl<W: Write> ImageEncoder for QoiImageEncoder<W> {
+    fn write_image(mut self, buf: &[u8], width: u32, height: u32, color_type: ColorType) -> ImageResult<()> {
+        let (layout, channels) = match color_type {
+            ColorType::Rgb8 => {(PixelLayout::RGB, Channels::RGB)}
+            ColorType::Rgba8 => {(PixelLayout::RGBA, Channels::RGBA)}
+            ColorType::L8 => {(PixelLayout::L8, Channels::RGB)}
+            ColorType::La8 => {(PixelLayout::LA8, Channels::RGBA)}
+            _ => {
+                return Err(encoding_error(format!("unsupported color type {:?}, only Rgb8, Rgba8, L8 and La8 can be encoded", color_type)));
+            }
+        };
+        let metadata = ImgMetadata {
+            width,
+            height,
+            channels,
+            colorspace: self.colorspace,
+        };
+
+        let encoded = crate::try_encode_with_layout(buf, layout, &metadata).map_err(encoding_error)?;
+        self.writer.write_all(&encoded).map_err(ImageError::IoError)
+    }
+}
+
+impl From<image::Rgba<u8>> for Pixel {
+    fn from(image::Rgba([r, g, b, a]): image::Rgba<u8>) -> Pixel {
+        Pixel::new(r, g, b, a)
+    }
+}
+
+impl From<Pixel> for image::Rgba<u8> {
+    fn from(pixel: Pixel) -> image::Rgba<u8> {
+        image::Rgba(pixel.into())
+    }
+}
+
+impl From<crate::QoiError> for ImageError {
+    fn from(err: crate::QoiError) -> ImageError {
+        ImageError::Decoding(DecodingError::new(ImageFormat::Qoi.into(), err))
+    }
+}
+
+fn encoding_error<E: Into<Box<dyn std::error::Error + Send + Sync>>>(err: E) -> ImageError {
+    ImageError::Encoding(EncodingError::new(ImageFormat::Qoi.into(), err))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use image::DynamicImage;
+
+    use super::*;
+
+    fn test_image() -> image::RgbaImage {
+        image::RgbaImage::from_fn(9, 4, |x, y| image::Rgba([x as u8 * 20, y as u8 * 60, 7, 255 - x as u8]))
+    }
+
+    #[test]
+    fn from_decoder() {
+        let source = test_image();
+        let metadata = ImgMetadata {
+            width: 9,
+            height: 4,
+            channels: Channels::RGBA,
+            colorspace: Colorspace::SrgbLinearAlpha,
+        };
+        let qoi = crate::encode(source.as_raw(), &metadata);
+
+        let decoder = QoiDecoder::new(qoi.as_slice()).unwrap();
+        let decoded = DynamicImage::from_decoder(decoder).expect("Decode should be successful");
+
+        assert_eq!(decoded.as_rgba8(), Some(&source));
+    }
+
+    #[test]
+    fn from_decoder_error() {
+        let mut qoi = crate::encode(&[1, 2, 3], &ImgMetadata {
+            width: 1,
+            height: 1,
+            channels: Channels::RGB,
+            colorspace: Colorspace::SrgbLinearAlpha,
+        });
+        qoi.truncate(qoi.len() - 1);
+
+        let decoder = QoiDecoder::new(qoi.as_slice()).unwrap();
+        assert!(matches!(DynamicImage::from_decoder(decoder), Err(ImageError::Decoding(_))));
+    }
+
+    #[test]
+    fn write_with_encoder() {
+        let source = DynamicImage::from(test_image());
+
+        let mut qoi = Vec::new();
+        source.write_with_encoder(QoiImageEncoder::new(&mut qoi)).expect("Encode should be successful");
+
+        let decoded = image::load(Cursor::new(qoi), ImageFormat::Qoi).expect("Decode should be successful");
+        assert_eq!(decoded, source);
+    }
+
+    #[test]
+    fn pixel_from_rgba() {
+        let source = test_image();
+        let pixels: Vec<Pixel> = source.pixels().map(|pixel| Pixel::from(*pixel)).collect();
+        let metadata = ImgMetadata {
+            width: 9,
+            height: 4,
+            channels: Channels::RGBA,
+            colorspace: Colorspace::SrgbLinearAlpha,
+        };
+
+        assert_eq!(crate::encode_pixels(&pixels, &metadata).unwrap(), crate::encode(source.as_raw(), &metadata));
+        assert_eq!(image::Rgba::from(pixels[5]), *source.get_pixel(5, 0));
+    }
+
+    #[test]
+    fn write_with_encoder_colorspace() {
+        let source = DynamicImage::from(image::RgbImage::new(2, 2));
+
+        let mut qoi = Vec::new();
+        source.write_with_encoder(QoiImageEncoder::new(&mut qoi).with_colorspace(Colorspace::AllLinearAlpha)).unwrap();
+
+        assert_eq!(crate::read_metadata(&qoi).unwrap().colorspace, Colorspace::AllLinearAlpha);
+    }
+
+    #[test]
+    fn write_with_encoder_gray() {
+        let source = DynamicImage::from(image::GrayAlphaImage::from_fn(5, 3, |x, y| image::LumaA([x as u8 * 50, y as u8 * 100])));
+
+        let mut qoi = Vec::new();
+        source.write_with_encoder(QoiImageEncoder::new(&mut qoi)).expect("Encode should be successful");
+
+        let decoded = image::load(Cursor::new(qoi), ImageFormat::Qoi).expect("Decode should be successful");
+        assert_eq!(decoded, DynamicImage::from(source.to_rgba8()));
+    }
+
+    #[test]
+    fn write_with_encoder_unsupported_color() {
+        let source = DynamicImage::new_rgb16(2, 2);
+
+        let result = source.write_with_encoder(QoiImageEncoder::new(Vec::new()));
+        assert!(matches!(result, Err(ImageError::Encoding(_))));
+    }
+}
@@ -0,0 +1,227 @@
+use crate::output::Output;
+use crate::{Channels, Pixel};
+
+/// Byte order of the pixels given to the encoder or written by the decoder.
+///
+/// Layouts without alpha are read as fully opaque and drop alpha when written. Grayscale layouts are read with
+/// r=g=b and written as the luminance of each pixel.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum PixelLayout {
+    RGB,
+    RGBA,
+    BGR,
+    BGRA,
+    ARGB,
+    ABGR,
+    L8,
+    LA8,
+}
+
+impl PixelLayout {
+    pub fn bytes_per_pixel(&self) -> usize {
+        match self {
+            PixelLayout::L8 => {1}
+            PixelLayout::LA8 => {2}
+            PixelLayout::RGB | PixelLayout::BGR => {3}
+            PixelLayout::RGBA | PixelLayout::BGRA | PixelLayout::ARGB | PixelLayout::ABGR => {4}
+        }
+    }
+
+    pub fn has_alpha(&self) -> bool {
+        match self {
+            PixelLayout::RGB | PixelLayout::BGR | PixelLayout::L8 => {false}
+            PixelLayout::RGBA | PixelLayout::BGRA | PixelLayout::ARGB | PixelLayout::ABGR | PixelLayout::LA8 => {true}
+        }
+    }
+
+    /// Reads the pixel at the start of `bytes`, which must hold at least [`PixelLayout::bytes_per_pixel`] bytes.
+    pub(crate) fn read_pixel(&self, bytes: &[u8]) -> Pixel {
+        let (r, g, b, a) = match self {
+            PixelLayout::RGB => {(bytes[0], bytes[1], bytes[2], 255)}
+            PixelLayout::RGBA => {(bytes[0], bytes[1], bytes[2], bytes[3])}
+            PixelLayout::BGR => {(bytes[2], bytes[1], bytes[0], 255)}
+            PixelLayout::BGRA => {(bytes[2], bytes[1], bytes[0], bytes[3])}
+            PixelLayout::ARGB => {(bytes[1], bytes[2], bytes[3], bytes[0])}
+            PixelLayout::ABGR => {(bytes[3], bytes[2], bytes[1], bytes[0])}
+            PixelLayout::L8 => {(bytes[0], bytes[0], bytes[0], 255)}
+            PixelLayout::LA8 => {(bytes[0], bytes[0], bytes[0], bytes[1])}
+        };
+        Pixel { r, g, b, a }
+    }
+
+    pub(crate) fn write_pixel(&self, bytes: &mut impl Output, pixel: &Pixel) {
+        let Pixel { r, g, b, a } = *pixel;
+        match self {
+            PixelLayout::RGB => {bytes.extend_from_slice(&[r, g, b])}
+            PixelLayout::RGBA => {bytes.extend_from_slice(&[r, g, b, a])}
+            PixelLayout::BGR => {bytes.extend_from_slice(&[b, g, r])}
+            PixelLayout::BGRA => {bytes.extend_from_slice(&[b, g, r, a])}
+            PixelLayout::ARGB => {bytes.extend_from_slice(&[a, r, g, b])}
+            PixelLayout::ABGR => {bytes.extend_from_slice(&[a, b, g, r])}
+            PixelLayout::L8 => {bytes.push(luminance(pixel))}
+            PixelLayout::LA8 => {bytes.extend_from_slice(&[luminance(pixel), a])}
+        }
+    }
+}
+
+/// Rec. 709 luminance, the same weights the `image` crate uses. Gray pixels keep their exact value.
+fn luminance(pixel: &Pixel) -> u8 {
+    let weighted = 2126 * pixel.r as u32 + 7152 * pixel.g as u32 + 722 * pixel.b as u32;
+    ((weighted + 5000) / 10000) as u8
+}
+
+/// Checks whether every pixel in `pixels`, stored in `layout`, has equal red, green and blue values.
+pub fn is_grayscale(pixels: &[u8], layout: PixelLayout) -> bool {
+    pixels.chunks_exact(layout.bytes_per_pixel()).all(|values| {
+        let pixel = layout.read_pixel(values);
+        pixel.r == pixel.g && pixel.g == pixel.b
+    })
+}
+
+/// A [`PixelLayout`] plus whether its color channels are premultiplied by alpha.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub(crate) struct PixelFormat {
+    pub(crate) layout: PixelLayout,
+    pub(crate) premultiplied: bool,
+}
+
+impl PixelFormat {
+    pub(crate) fn bytes_per_pixel(&self) -> usize {
+        self.layout.bytes_per_pixel()
+    }
+
+    /// Reads the pixel at the start of `bytes` as straight alpha.
+    pub(crate) fn read_pixel(&self, bytes: &[u8]) -> Pixel {
+        let pixel = self.layout.read_pixel(bytes);
+        match self.premultiplied {
+            true => {unpremultiply(&pixel)}
+            false => {pixel}
+        }
+    }
+
+    /// Writes the straight alpha `pixel`, premultiplying it first if needed.
+    pub(crate) fn write_pixel(&self, bytes: &mut impl Output, pixel: &Pixel) {
+        match self.premultiplied {
+            true => {self.layout.write_pixel(bytes, &premultiply(pixel))}
+            false => {self.layout.write_pixel(bytes, pixel)}
+        }
+    }
+}
+
+impl From<PixelLayout> for PixelFormat {
+    fn from(layout: PixelLayout) -> PixelFormat {
+        PixelFormat { layout, premultiplied: false }
+    }
+}
+
+/// Multiplies each color channel by alpha, rounding to the nearest value.
+pub(crate) fn premultiply(pixel: &Pixel) -> Pixel {
+    let a = pixel.a as u32;
+    let scale = |channel: u8| ((channel as u32 * a + 127) / 255) as u8;
+    Pixel { r: scale(pixel.r), g: scale(pixel.g), b: scale(pixel.b), a: pixel.a }
+}
+
+/// Divides each color channel by alpha, rounding to the nearest value.
+///
+/// Fully transparent pixels become transparent black, and channels larger than alpha are clamped to 255.
+pub(crate) fn unpremultiply(pixel: &Pixel) -> Pixel {
+    let a = pixel.a as u32;
+    if a == 0 {
+        return Pixel { r: 0, g: 0, b: 0, a: 0 };
+    }
+    let scale = |channel: u8| ((channel as u32 * 255 + a / 2) / a).min(255) as u8;
+    Pixel { r: scale(pixel.r), g: scale(pixel.g), b: scale(pixel.b), a: pixel.a }
+}
+
+impl From<&Channels> for PixelLayout {
+    fn from(channels: &Channels) -> PixelLayout {
+        match channels {
+            Channels::RGB => {PixelLayout::RGB}
+            Channels::RGBA => {PixelLayout::RGBA}
+        }
+    }
+}
+
+#[cfg(all(test, feature = "alloc"))]
+mod tests {
+    use super::*;
+
+    const LAYOUTS: [PixelLayout; 8] = [PixelLayout::RGB, PixelLayout::RGBA, PixelLayout::BGR, PixelLayout::BGRA, PixelLayout::ARGB, PixelLayout::ABGR, PixelLayout::L8, PixelLayout::LA8];
+
+    #[test]
+    fn write_pixel_order() {
+        let pixel = Pixel { r: 1, g: 2, b: 3, a: 4 };
+        let expected: [&[u8]; 8] = [&[1, 2, 3], &[1, 2, 3, 4], &[3, 2, 1], &[3, 2, 1, 4], &[4, 1, 2, 3], &[4, 3, 2, 1], &[2], &[2, 4]];
+
+        for (layout, expected) in LAYOUTS.iter().zip(expected) {
+            let mut bytes = Vec::new();
+            layout.write_pixel(&mut bytes, &pixel);
+            assert_eq!(bytes, expected, "{:?}", layout);
+            assert_eq!(bytes.len(), layout.bytes_per_pixel());
+        }
+    }
+
+    #[test]
+    fn read_pixel_round_trip() {
+        let pixel = Pixel { r: 30, g: 30, b: 30, a: 40 };
+        let opaque = Pixel { a: 255, ..pixel };
+
+        for layout in LAYOUTS {
+            let mut bytes = Vec::new();
+            layout.write_pixel(&mut bytes, &pixel);
+            let expected = if layout.has_alpha() {pixel} else {opaque};
+            assert_eq!(layout.read_pixel(&bytes), expected, "{:?}", layout);
+        }
+    }
+
+    #[test]
+    fn luminance_weights() {
+        assert_eq!(luminance(&Pixel { r: 255, g: 0, b: 0, a: 255 }), 54);
+        assert_eq!(luminance(&Pixel { r: 0, g: 255, b: 0, a: 255 }), 182);
+        assert_eq!(luminance(&Pixel { r: 0, g: 0, b: 255, a: 255 }), 18);
+        for value in 0..=255 {
+            assert_eq!(luminance(&Pixel { r: value, g: value, b: value, a: 0 }), value);
+        }
+    }
+
+    #[test]
+    fn grayscale_check() {
+        assert!(is_grayscale(&[0, 0, 0, 7, 7, 7, 255, 255, 255], PixelLayout::RGB));
+        assert!(is_grayscale(&[9, 9, 9, 0, 9, 9, 9, 255], PixelLayout::RGBA));
+        assert!(!is_grayscale(&[0, 0, 0, 7, 7, 8], PixelLayout::RGB));
+        assert!(is_grayscale(&[1, 9, 9, 9], PixelLayout::ARGB));
+        assert!(!is_grayscale(&[9, 9, 9, 1], PixelLayout::ARGB));
+    }
+
+    #[test]
+    fn premultiply_rounding() {
+        assert_eq!(premultiply(&Pixel { r: 255, g: 128, b: 1, a: 128 }), Pixel { r: 128, g: 64, b: 1, a: 128 });
+        assert_eq!(premultiply(&Pixel { r: 200, g: 100, b: 50, a: 0 }), Pixel { r: 0, g: 0, b: 0, a: 0 });
+        assert_eq!(unpremultiply(&Pixel { r: 128, g: 64, b: 1, a: 128 }), Pixel { r: 255, g: 128, b: 2, a: 128 });
+        assert_eq!(unpremultiply(&Pixel { r: 9, g: 200, b: 0, a: 10 }), Pixel { r: 230, g: 255, b: 0, a: 10 });
+        assert_eq!(unpremultiply(&Pixel { r: 9, g: 9, b: 9, a: 0 }), Pixel { r: 0, g: 0, b: 0, a: 0 });
+    }
+
+    #[test]
+    fn premultiplied_round_trip() {
+        for a in 0..=255u8 {
+            for c in 0..=255u8 {
+                //premultiplied values always survive un-premultiplying and premultiplying again
+                if c <= a {
+                    let premultiplied = Pixel { r: c, g: c, b: c, a };
+                    assert_eq!(premultiply(&unpremultiply(&premultiplied)), premultiplied);
+                }
+
+                //straight values only survive when opaque, the error grows as alpha shrinks
+                let straight = Pixel { r: c, g: c, b: c, a };
+                let round_trip = unpremultiply(&premultiply(&straight));
+                let error = (round_trip.r as f32 - c as f32).abs();
+                match a {
+                    255 => {assert_eq!(round_trip, straight)}
+                    0 => {assert_eq!(round_trip.r, 0)}
+                    _ => {assert!(error <= 127.5 / a as f32 + 0.5, "c {} a {} gave {}", c, a, round_trip.r)}
+                }
+            }
+        }
+    }
+}
@@ -1,5 +1,46 @@
+#![cfg_attr(not(any(feature = "std", test)), no_std)]
+
+#[cfg(feature = "alloc")]
+extern crate alloc;
+
+#[cfg(feature = "alloc")]
+use alloc::vec::Vec;
+
 mod encoder;
 mod decoder;
+mod layout;
+#[cfg(feature = "std")]
+mod linear;
+mod output;
+#[cfg(feature = "std")]
+mod parallel;
+mod seek;
+mod simd;
+#[cfg(feature = "std")]
+mod stream;
+#[cfg(feature = "image")]
+mod image_codec;
+#[cfg(all(test, feature = "alloc"))]
+mod test_util;
+
+use layout::PixelFormat;
+use output::{Output, SliceOutput};
+
+pub use decoder::{decode_into, decode_into_premultiplied, decode_into_with_layout, decode_to_slice, read_metadata, DecodeLimits, DecodeOptions, Limit, QoiError, Strictness};
+#[cfg(feature = "alloc")]
+pub use decoder::{decode_premultiplied, decode_with_layout};
+pub use encoder::EncodeError;
+pub use layout::{is_grayscale, PixelLayout};
+#[cfg(feature = "alloc")]
+pub use seek::{decode_rows, try_encode_with_seek_table, SeekTable};
+#[cfg(feature = "std")]
+pub use linear::{decode_linear, encode_linear};
+#[cfg(feature = "std")]
+pub use parallel::{decode_parallel, try_encode_parallel};
+#[cfg(feature = "std")]
+pub use stream::{read_metadata_from, QoiDecoder, QoiEncoder};
+#[cfg(feature = "image")]
+pub use image_codec::QoiImageEncoder;
 
 #[derive(Eq, PartialEq, Debug)]
 pub enum Channels {
@@ -21,6 +62,7 @@ pub struct ImgMetadata {
     pub colorspace: Colorspace
 }
 
+#[allow(clippy::enum_variant_names)]
 #[derive(Eq, PartialEq, Debug)]
 enum Operation {
     QoiOpRgb,
@@ -31,6 +73,12 @@ enum Operation {
     QoiOpRun,
 }
 
+/// The largest number of pixels an image may have, as recommended by the QOI specification.
+pub const QOI_PIXELS_MAX: u64 = 400_000_000;
+
+const HEADER_SIZE: usize = 14;
+const END_MARKER: [u8; 8] = [0, 0, 0, 0, 0, 0, 0, 1];
+
 const QOI_OP_RGB: u8 = 0b11111110;
 const QOI_OP_RGBA: u8 = 0b11111111;
 const QOI_OP_INDEX: u8 = 0b00;
@@ -38,42 +86,185 @@ const QOI_OP_DIFF: u8 = 0b01;
 const QOI_OP_LUMA: u8 = 0b10;
 const QOI_OP_RUN: u8 = 0b11;
 
-//todo - make private again once index calculation is moved
+/// A single RGBA pixel with 8 bits per channel.
 #[derive(Copy, Clone, Eq, PartialEq, Debug)]
 pub struct Pixel {
     r: u8,
     g: u8,
     b: u8,
-    a:u8,
+    a: u8,
+}
+
+impl Pixel {
+    /// Creates a pixel from its red, green, blue and alpha channels.
+    pub fn new(r: u8, g: u8, b: u8, a: u8) -> Pixel {
+        Pixel { r, g, b, a }
+    }
+
+    /// Creates a fully opaque pixel.
+    pub fn rgb(r: u8, g: u8, b: u8) -> Pixel {
+        Pixel { r, g, b, a: 255 }
+    }
+
+    pub fn r(&self) -> u8 {
+        self.r
+    }
+
+    pub fn g(&self) -> u8 {
+        self.g
+    }
+
+    pub fn b(&self) -> u8 {
+        self.b
+    }
+
+    pub fn a(&self) -> u8 {
+        self.a
+    }
 }
 
+impl From<[u8; 3]> for Pixel {
+    fn from([r, g, b]: [u8; 3]) -> Pixel {
+        Pixel::rgb(r, g, b)
+    }
+}
 
-pub fn encode(rgb_pixels: &Vec<u8>, metadata: &ImgMetadata) -> Vec<u8> {
+impl From<[u8; 4]> for Pixel {
+    fn from([r, g, b, a]: [u8; 4]) -> Pixel {
+        Pixel::new(r, g, b, a)
+    }
+}
+
+impl From<Pixel> for [u8; 4] {
+    fn from(pixel: Pixel) -> [u8; 4] {
+        [pixel.r, pixel.g, pixel.b, pixel.a]
+    }
+}
+
+
+/// Encodes raw pixels as a QOI file, panicking if they don't match `metadata`. See [`try_encode`] for a non-panicking version.
+#[cfg(feature = "alloc")]
+pub fn encode(rgb_pixels: &[u8], metadata: &ImgMetadata) -> Vec<u8> {
+    match try_encode(rgb_pixels, metadata) {
+        Ok(encoded) => encoded,
+        Err(err) => panic!("Invalid image: {}", err),
+    }
+}
+
+/// Encodes raw pixels as a QOI file.
+///
+/// `rgb_pixels` must hold exactly `width * height` pixels of 3 (RGB) or 4 (RGBA) bytes each, as given by `metadata`.
+#[cfg(feature = "alloc")]
+pub fn try_encode(rgb_pixels: &[u8], metadata: &ImgMetadata) -> Result<Vec<u8>, EncodeError> {
+    try_encode_with_layout(rgb_pixels, PixelLayout::from(&metadata.channels), metadata)
+}
+
+/// Encodes pixels stored in `layout` as a QOI file, reordering the channels as each pixel is read.
+///
+/// The header's channel count comes from `metadata`, so alpha in the input is ignored when encoding an RGB image.
+#[cfg(feature = "alloc")]
+pub fn try_encode_with_layout(pixels: &[u8], layout: PixelLayout, metadata: &ImgMetadata) -> Result<Vec<u8>, EncodeError> {
     let mut raw_bytes: Vec<u8> = Vec::new();
+    encoder::encode(&mut raw_bytes, pixels, &PixelFormat::from(layout), metadata)?;
+    Ok(raw_bytes)
+}
+
+/// Encodes pixels whose color channels are premultiplied by alpha, converting them back to straight alpha as QOI stores.
+///
+/// Each channel becomes `(channel * 255 + alpha / 2) / alpha` clamped to 255, which rounds to the nearest value.
+/// Fully transparent pixels are stored as transparent black. Decoding with [`decode_premultiplied`] gives back the
+/// same values, but straight alpha colors of low alpha pixels only keep about `alpha + 1` distinct levels.
+#[cfg(feature = "alloc")]
+pub fn try_encode_premultiplied(pixels: &[u8], layout: PixelLayout, metadata: &ImgMetadata) -> Result<Vec<u8>, EncodeError> {
+    let mut raw_bytes: Vec<u8> = Vec::new();
+    encoder::encode(&mut raw_bytes, pixels, &PixelFormat { layout, premultiplied: true }, metadata)?;
+    Ok(raw_bytes)
+}
+
+/// Encodes raw pixels into `out` without allocating, returning the number of bytes written.
+///
+/// Fails with [`EncodeError::OutputTooSmall`] if the encoded file doesn't fit; [`max_encoded_len`] is always enough.
+pub fn encode_to_slice(rgb_pixels: &[u8], metadata: &ImgMetadata, out: &mut [u8]) -> Result<usize, EncodeError> {
+    let available = out.len();
+    let mut output = SliceOutput::new(out);
+    encoder::encode(&mut output, rgb_pixels, &PixelFormat::from(PixelLayout::from(&metadata.channels)), metadata)?;
+
+    if output.overflowed() {
+        return Err(EncodeError::OutputTooSmall { required: output.len(), available });
+    }
+    Ok(output.len())
+}
+
+/// Encodes a `metadata.width` by `metadata.height` region of a larger frame as a QOI file.
+///
+/// The region starts at pixel `x`, `y` of `pixels`, whose rows are `stride_bytes` apart and hold pixels stored in `layout`.
+/// Padding between rows and pixels outside the region are never read, so framebuffers can be encoded without packing them first.
+#[cfg(feature = "alloc")]
+pub fn try_encode_region(pixels: &[u8], layout: PixelLayout, stride_bytes: usize, x: u32, y: u32, metadata: &ImgMetadata) -> Result<Vec<u8>, EncodeError> {
+    let mut raw_bytes: Vec<u8> = Vec::new();
+    encoder::encode_region(&mut raw_bytes, pixels, &PixelFormat::from(layout), stride_bytes, x, y, metadata)?;
+    Ok(raw_bytes)
+}
+
+/// Encodes a region as in [`try_encode_region`] into `out` without allocating, returning the number of bytes written.
+pub fn encode_region_to_slice(pixels: &[u8], layout: PixelLayout, stride_bytes: usize, x: u32, y: u32, metadata: &ImgMetadata, out: &mut [u8]) -> Result<usize, EncodeError> {
+    let available = out.len();
+    let mut output = SliceOutput::new(out);
+    encoder::encode_region(&mut output, pixels, &PixelFormat::from(layout), stride_bytes, x, y, metadata)?;
+
+    if output.overflowed() {
+        return Err(EncodeError::OutputTooSmall { required: output.len(), available });
+    }
+    Ok(output.len())
+}
 
-    encoder::add_header(&mut raw_bytes, &metadata);
+/// The largest possible size of the QOI file for an image described by `metadata`.
+pub fn max_encoded_len(metadata: &ImgMetadata) -> usize {
+    encoder::max_encoded_len(metadata)
+}
 
-    let alpha_included = match metadata.channels {
-        Channels::RGB => {false}
-        Channels::RGBA => {true}
-    };
-    encoder::add_chunks(&mut raw_bytes, &rgb_pixels, alpha_included).unwrap();
 
-    encoder::add_end_marker(&mut raw_bytes);
+/// Encodes `pixels` as a QOI file. There must be exactly `width * height` of them.
+///
+/// Alpha is ignored when `metadata` describes an RGB image.
+#[cfg(feature = "alloc")]
+pub fn encode_pixels(pixels: &[Pixel], metadata: &ImgMetadata) -> Result<Vec<u8>, EncodeError> {
+    let mut raw_bytes: Vec<u8> = Vec::new();
+    encoder::encode_pixels(&mut raw_bytes, pixels, metadata)?;
+    Ok(raw_bytes)
+}
 
-    raw_bytes
+/// Decodes a QOI file, panicking if it is malformed. See [`try_decode`] for a non-panicking version.
+#[cfg(feature = "alloc")]
+pub fn decode(raw_file_bytes: &[u8]) -> (ImgMetadata, Vec<u8>) {
+    match try_decode(raw_file_bytes) {
+        Ok(decoded) => decoded,
+        Err(err) => panic!("Invalid QOI file: {}", err),
+    }
 }
 
+/// Decodes a QOI file into its metadata and raw pixel values.
+///
+/// Pixels are returned row by row as RGB or RGBA bytes, matching the channels in the header.
+/// See [`decode_to_slice`] for a version that writes into a caller-provided buffer, and [`decode_with_layout`] for other byte orders.
+/// Images over [`QOI_PIXELS_MAX`] pixels are rejected; use [`DecodeOptions::with_limits`] to decode untrusted files with tighter limits.
+#[cfg(feature = "alloc")]
+pub fn try_decode(raw_file_bytes: &[u8]) -> Result<(ImgMetadata, Vec<u8>), QoiError> {
+    decoder::decode(raw_file_bytes)
+}
 
-pub fn decode(raw_file_bytes: &Vec<u8>) -> (ImgMetadata, Vec<u8>) {
-    return decoder::decode(&raw_file_bytes);
+/// Decodes a QOI file into its metadata and pixels. Pixels of RGB images are fully opaque.
+#[cfg(feature = "alloc")]
+pub fn decode_pixels(raw_file_bytes: &[u8]) -> Result<(ImgMetadata, Vec<Pixel>), QoiError> {
+    let (metadata, decoded) = decoder::decode_with_layout(raw_file_bytes, PixelLayout::RGBA)?;
+    let pixels = decoded.chunks_exact(4).map(|values| Pixel::new(values[0], values[1], values[2], values[3])).collect();
+    Ok((metadata, pixels))
 }
 
-#[cfg(test)]
+#[cfg(all(test, feature = "alloc"))]
 mod tests {
     use std::io::Cursor;
 
-    use image;
 
     use super::*;
 
@@ -107,8 +298,311 @@ mod tests {
         assert!(source_image.eq(output_image));
     }
 
+    #[test]
+    fn test_try_encode_errors() {
+        let metadata = ImgMetadata {
+            width: 2,
+            height: 2,
+            channels: Channels::RGBA,
+            colorspace: Colorspace::SrgbLinearAlpha,
+        };
+        assert_eq!(try_encode(&[0; 12], &metadata), Err(EncodeError::BufferSizeMismatch { expected: 16, actual: 12 }));
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_encode_panics_on_error() {
+        let metadata = ImgMetadata {
+            width: 2,
+            height: 2,
+            channels: Channels::RGB,
+            colorspace: Colorspace::SrgbLinearAlpha,
+        };
+        encode(&[0; 3], &metadata);
+    }
+
+    #[test]
+    fn test_encode_region() {
+        let frame = create_random_image(6, 4);
+        let metadata = ImgMetadata {
+            width: 3,
+            height: 2,
+            channels: Channels::RGB,
+            colorspace: Colorspace::SrgbLinearAlpha,
+        };
+        let region = image::imageops::crop_imm(&frame, 2, 1, 3, 2).to_image();
+
+        let qoi = try_encode_region(frame.as_raw(), PixelLayout::RGB, 18, 2, 1, &metadata).expect("Encode should be successful");
+        assert_eq!(qoi, encode(region.as_raw(), &metadata));
+
+        let mut out = vec![0; max_encoded_len(&metadata)];
+        let written = encode_region_to_slice(frame.as_raw(), PixelLayout::RGB, 18, 2, 1, &metadata, &mut out).expect("Encode should be successful");
+        assert_eq!(&out[..written], qoi.as_slice());
+    }
+
+    #[test]
+    fn test_encode_with_layout() {
+        let rgba = [10, 20, 30, 40, 10, 20, 30, 40, 200, 100, 0, 255];
+        let bgra = [30, 20, 10, 40, 30, 20, 10, 40, 0, 100, 200, 255];
+        let mut metadata = ImgMetadata {
+            width: 3,
+            height: 1,
+            channels: Channels::RGBA,
+            colorspace: Colorspace::SrgbLinearAlpha,
+        };
+
+        let qoi = try_encode_with_layout(&bgra, PixelLayout::BGRA, &metadata).expect("Encode should be successful");
+        assert_eq!(qoi, encode(&rgba, &metadata));
+
+        //alpha is dropped for RGB images
+        metadata.channels = Channels::RGB;
+        let qoi = try_encode_with_layout(&bgra, PixelLayout::BGRA, &metadata).expect("Encode should be successful");
+        assert_eq!(qoi, encode(&[10, 20, 30, 10, 20, 30, 200, 100, 0], &metadata));
+
+        let err = try_encode_with_layout(&bgra, PixelLayout::BGR, &metadata).unwrap_err();
+        assert_eq!(err, EncodeError::BufferSizeMismatch { expected: 9, actual: 12 });
+    }
+
+    #[test]
+    fn test_decode_with_layout() {
+        let metadata = ImgMetadata {
+            width: 2,
+            height: 1,
+            channels: Channels::RGBA,
+            colorspace: Colorspace::SrgbLinearAlpha,
+        };
+        let qoi = encode(&[1, 2, 3, 4, 5, 6, 7, 8], &metadata);
+
+        let (_, argb) = decode_with_layout(&qoi, PixelLayout::ARGB).expect("Decode should be successful");
+        assert_eq!(argb, [4, 1, 2, 3, 8, 5, 6, 7]);
+
+        let mut out = [0; 6];
+        let (_, written) = decode_into_with_layout(&qoi, &mut out, PixelLayout::BGR).expect("Decode should be successful");
+        assert_eq!(written, 6);
+        assert_eq!(out, [3, 2, 1, 7, 6, 5]);
+    }
+
+    #[test]
+    fn test_grayscale_round_trip() {
+        let gray = [0, 40, 80, 120, 160, 200];
+        let metadata = ImgMetadata {
+            width: 3,
+            height: 2,
+            channels: Channels::RGB,
+            colorspace: Colorspace::SrgbLinearAlpha,
+        };
+
+        let qoi = try_encode_with_layout(&gray, PixelLayout::L8, &metadata).expect("Encode should be successful");
+        let (_, rgb) = try_decode(&qoi).expect("Decode should be successful");
+        assert!(is_grayscale(&rgb, PixelLayout::RGB));
+        assert_eq!(rgb[9..12], [120, 120, 120]);
+
+        let (_, decoded) = decode_with_layout(&qoi, PixelLayout::L8).expect("Decode should be successful");
+        assert_eq!(decoded, gray);
+    }
+
+    #[test]
+    fn test_gray_alpha_round_trip() {
+        let gray_alpha = [10, 255, 10, 0, 250, 128];
+        let metadata = ImgMetadata {
+            width: 3,
+            height: 1,
+            channels: Channels::RGBA,
+            colorspace: Colorspace::SrgbLinearAlpha,
+        };
+
+        let qoi = try_encode_with_layout(&gray_alpha, PixelLayout::LA8, &metadata).expect("Encode should be successful");
+        let (_, rgba) = try_decode(&qoi).expect("Decode should be successful");
+        assert_eq!(rgba, [10, 10, 10, 255, 10, 10, 10, 0, 250, 250, 250, 128]);
+
+        let (_, decoded) = decode_with_layout(&qoi, PixelLayout::LA8).expect("Decode should be successful");
+        assert_eq!(decoded, gray_alpha);
+    }
+
+    #[test]
+    fn test_pixel_conversions() {
+        let pixel = Pixel::new(1, 2, 3, 4);
+        assert_eq!((pixel.r(), pixel.g(), pixel.b(), pixel.a()), (1, 2, 3, 4));
+        assert_eq!(Pixel::from([1, 2, 3, 4]), pixel);
+        assert_eq!(<[u8; 4]>::from(pixel), [1, 2, 3, 4]);
+
+        assert_eq!(Pixel::from([1, 2, 3]), Pixel::rgb(1, 2, 3));
+        assert_eq!(Pixel::rgb(1, 2, 3).a(), 255);
+    }
+
+    #[test]
+    fn test_encode_pixels() {
+        let pixels = [Pixel::new(10, 20, 30, 40), Pixel::new(10, 20, 30, 40), Pixel::rgb(200, 100, 0)];
+        let mut metadata = ImgMetadata {
+            width: 3,
+            height: 1,
+            channels: Channels::RGBA,
+            colorspace: Colorspace::SrgbLinearAlpha,
+        };
+
+        let qoi = encode_pixels(&pixels, &metadata).expect("Encode should be successful");
+        assert_eq!(qoi, encode(&[10, 20, 30, 40, 10, 20, 30, 40, 200, 100, 0, 255], &metadata));
+        let (decoded_metadata, decoded) = decode_pixels(&qoi).expect("Decode should be successful");
+        assert_eq!(decoded_metadata, metadata);
+        assert_eq!(decoded, pixels);
+
+        metadata.channels = Channels::RGB;
+        let qoi = encode_pixels(&pixels, &metadata).expect("Encode should be successful");
+        assert_eq!(qoi, encode(&[10, 20, 30, 10, 20, 30, 200, 100, 0], &metadata));
+        let (_, decoded) = decode_pixels(&qoi).expect("Decode should be successful");
+        assert_eq!(decoded[0], Pixel::rgb(10, 20, 30));
+
+        let err = encode_pixels(&pixels[..2], &metadata).unwrap_err();
+        assert_eq!(err, EncodeError::PixelCountMismatch { expected: 3, actual: 2 });
+    }
+
+    #[test]
+    fn test_premultiplied_round_trip() {
+        //opaque, half transparent, low alpha and fully transparent pixels
+        let straight = [200, 100, 50, 255, 200, 100, 50, 128, 200, 100, 50, 3, 200, 100, 50, 0];
+        let premultiplied = [200, 100, 50, 255, 100, 50, 25, 128, 2, 1, 1, 3, 0, 0, 0, 0];
+        let metadata = ImgMetadata {
+            width: 4,
+            height: 1,
+            channels: Channels::RGBA,
+            colorspace: Colorspace::SrgbLinearAlpha,
+        };
+
+        let qoi = encode(&straight, &metadata);
+        let (_, decoded) = decode_premultiplied(&qoi, PixelLayout::RGBA).expect("Decode should be successful");
+        assert_eq!(decoded, premultiplied);
+
+        //premultiplied values come back unchanged
+        let qoi = try_encode_premultiplied(&premultiplied, PixelLayout::RGBA, &metadata).expect("Encode should be successful");
+        let mut out = [0; 16];
+        decode_into_premultiplied(&qoi, &mut out, PixelLayout::RGBA).expect("Decode should be successful");
+        assert_eq!(out, premultiplied);
+
+        //but only opaque pixels keep their exact straight colors, low alpha and fully transparent ones lose the most
+        let (_, decoded) = try_decode(&qoi).expect("Decode should be successful");
+        assert_eq!(decoded[..4], straight[..4]);
+        assert_eq!(decoded[4..], [199, 100, 50, 128, 170, 85, 85, 3, 0, 0, 0, 0]);
+    }
+
     #[test]
     fn test_decode() {
+        let source_image = create_random_image(3, 5);
+
+        let mut qoi = Vec::new();
+        image::DynamicImage::from(source_image.clone())
+            .write_to(&mut Cursor::new(&mut qoi), image::ImageFormat::Qoi)
+            .expect("Encode should be successful");
+
+        let (metadata, pixels) = try_decode(&qoi).expect("Decode should be successful");
+        assert_eq!(metadata.width, 3);
+        assert_eq!(metadata.height, 5);
+        assert_eq!(metadata.channels, Channels::RGB);
+        assert_eq!(&pixels, source_image.as_raw());
+    }
+
+    #[test]
+    fn test_try_decode_error() {
+        let err = try_decode(b"qoix").unwrap_err();
+        assert_eq!(err, QoiError::Truncated { offset: 4 });
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_decode_panics_on_error() {
+        decode(b"not a qoi file");
+    }
+
+    #[test]
+    fn test_encode_to_slice() {
+        let source_image = create_random_image(4, 3);
+        let metadata = ImgMetadata {
+            width: 4,
+            height: 3,
+            channels: Channels::RGB,
+            colorspace: Colorspace::SrgbLinearAlpha,
+        };
+        let expected = encode(source_image.as_raw(), &metadata);
+
+        let mut out = vec![0; max_encoded_len(&metadata)];
+        let written = encode_to_slice(source_image.as_raw(), &metadata, &mut out).expect("Encode should be successful");
+        assert_eq!(&out[..written], expected.as_slice());
+    }
+
+    #[test]
+    fn test_encode_to_slice_too_small() {
+        let source_image = create_random_image(4, 3);
+        let metadata = ImgMetadata {
+            width: 4,
+            height: 3,
+            channels: Channels::RGB,
+            colorspace: Colorspace::SrgbLinearAlpha,
+        };
+        let required = encode(source_image.as_raw(), &metadata).len();
+
+        let mut out = vec![0; required - 1];
+        let err = encode_to_slice(source_image.as_raw(), &metadata, &mut out).unwrap_err();
+        assert_eq!(err, EncodeError::OutputTooSmall { required, available: required - 1 });
+    }
+
+    #[test]
+    fn test_decode_to_slice() {
+        let source_image = create_random_image(3, 5);
+        let metadata = ImgMetadata {
+            width: 3,
+            height: 5,
+            channels: Channels::RGB,
+            colorspace: Colorspace::SrgbLinearAlpha,
+        };
+        let qoi = encode(source_image.as_raw(), &metadata);
+
+        //extra room is left untouched
+        let mut out = vec![7; 50];
+        let (decoded_metadata, written) = decode_to_slice(&qoi, &mut out).expect("Decode should be successful");
+        assert_eq!(decoded_metadata, metadata);
+        assert_eq!(written, 45);
+        assert_eq!(&out[..written], source_image.as_raw().as_slice());
+        assert_eq!(&out[written..], &[7; 5]);
+
+        let err = decode_to_slice(&qoi, &mut out[..44]).unwrap_err();
+        assert_eq!(err, QoiError::OutputTooSmall { offset: 14, required: 45, available: 44 });
+    }
+
+    #[test]
+    fn test_decode_into_rgba() {
+        let source_image = create_random_image(3, 5);
+        let metadata = ImgMetadata {
+            width: 3,
+            height: 5,
+            channels: Channels::RGB,
+            colorspace: Colorspace::SrgbLinearAlpha,
+        };
+        let qoi = encode(source_image.as_raw(), &metadata);
+
+        let mut out = vec![0; 60];
+        let (decoded_metadata, written) = decode_into(&qoi, &mut out, Channels::RGBA).expect("Decode should be successful");
+        //the header still describes the file
+        assert_eq!(decoded_metadata.channels, Channels::RGB);
+        assert_eq!(written, 60);
+        let expected = image::DynamicImage::from(source_image).to_rgba8();
+        assert_eq!(&out, expected.as_raw());
+
+        let err = decode_into(&qoi, &mut out[..45], Channels::RGBA).unwrap_err();
+        assert_eq!(err, QoiError::OutputTooSmall { offset: 14, required: 60, available: 45 });
+    }
+
+    #[test]
+    fn test_decode_into_rgb() {
+        let metadata = ImgMetadata {
+            width: 2,
+            height: 1,
+            channels: Channels::RGBA,
+            colorspace: Colorspace::SrgbLinearAlpha,
+        };
+        let qoi = encode(&[10, 20, 30, 0, 10, 20, 30, 128], &metadata);
 
+        let mut out = [0; 6];
+        let (_, written) = decode_into(&qoi, &mut out, Channels::RGB).expect("Decode should be successful");
+        assert_eq!(written, 6);
+        assert_eq!(out, [10, 20, 30, 10, 20, 30]);
     }
 }
@@ -0,0 +1,167 @@
+//! Conversion between QOI's 8 bit values and linear light `f32` values.
+//!
+//! The header's colorspace decides whether color channels use the sRGB transfer function. Conversions go through
+//! [`DecodeOptions`] on the way in, so limits and strictness apply as for any other decode.
+
+use core::mem;
+
+use crate::layout::{PixelFormat, PixelLayout};
+use crate::{encoder, read_metadata, Colorspace, DecodeOptions, EncodeError, ImgMetadata, QoiError};
+
+/// Converts an sRGB encoded value in `0.0..=1.0` to linear light.
+fn srgb_to_linear(value: f32) -> f32 {
+    if value <= 0.04045 {
+        value / 12.92
+    } else {
+        ((value + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+/// Converts a linear light value in `0.0..=1.0` to sRGB encoding.
+fn linear_to_srgb(value: f32) -> f32 {
+    if value <= 0.0031308 {
+        value * 12.92
+    } else {
+        1.055 * value.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+/// Decodes a QOI file into linear light `f32` RGBA values between 0 and 1, four per pixel.
+///
+/// Color channels go through the sRGB transfer function when the header says [`Colorspace::SrgbLinearAlpha`] and are
+/// only scaled for [`Colorspace::AllLinearAlpha`]. Alpha is always linear. Pixels of RGB images are fully opaque.
+/// See [`DecodeOptions::decode_linear`] to change the limits or strictness.
+pub fn decode_linear(bytes: &[u8]) -> Result<(ImgMetadata, Vec<f32>), QoiError> {
+    DecodeOptions::new().decode_linear(bytes)
+}
+
+impl DecodeOptions {
+    /// Decodes into linear light values like [`decode_linear`], with these options' limits and strictness.
+    ///
+    /// The layout and premultiplied alpha settings are ignored, since the values are always straight RGBA. The
+    /// allocation limit counts the 8 bit pixels and the `f32` values, which are both held at the end of decoding.
+    pub fn decode_linear(&self, bytes: &[u8]) -> Result<(ImgMetadata, Vec<f32>), QoiError> {
+        self.limits.check_bytes_per_pixel(&read_metadata(bytes)?, 4 + 4 * mem::size_of::<f32>())?;
+        let (metadata, decoded) = self.clone().with_layout(PixelLayout::RGBA).with_premultiplied_alpha(false).decode(bytes)?;
+
+        let mut color_table = [0.0; 256];
+        for (value, linear) in color_table.iter_mut().enumerate() {
+            let value = value as f32 / 255.0;
+            *linear = match metadata.colorspace {
+                Colorspace::SrgbLinearAlpha => {srgb_to_linear(value)}
+                Colorspace::AllLinearAlpha => {value}
+            };
+        }
+
+        let linear = decoded.chunks_exact(4)
+            .flat_map(|pixel| [color_table[pixel[0] as usize], color_table[pixel[1] as usize], color_table[pixel[2] as usize], pixel[3] as f32 / 255.0])
+            .collect();
+
+        Ok((metadata, linear))
+    }
+}
+
+/// Encodes linear light `f32` pixels as a QOI file in the colorspace given by `metadata`.
+///
+/// `pixels` holds 3 (RGB) or 4 (RGBA) values per pixel as given by `metadata`, and sizes in
+/// [`EncodeError::BufferSizeMismatch`] count values rather than bytes. Values are clamped to `0.0..=1.0`, converted
+/// with the sRGB transfer function for [`Colorspace::SrgbLinearAlpha`] and rounded to the nearest 8 bit value.
+pub fn encode_linear(pixels: &[f32], metadata: &ImgMetadata) -> Result<Vec<u8>, EncodeError> {
+    let channels_per_pixel = encoder::channels_per_pixel(metadata);
+
+    let mut quantized = Vec::with_capacity(pixels.len());
+    for (i, value) in pixels.iter().enumerate() {
+        let value = value.clamp(0.0, 1.0);
+        let is_alpha = i % channels_per_pixel == 3;
+        let encoded = match metadata.colorspace {
+            Colorspace::SrgbLinearAlpha if !is_alpha => {linear_to_srgb(value)}
+            _ => {value}
+        };
+        quantized.push((encoded * 255.0).round() as u8);
+    }
+
+    let mut raw_bytes = Vec::new();
+    encoder::encode(&mut raw_bytes, &quantized, &PixelFormat::from(PixelLayout::from(&metadata.channels)), metadata)?;
+    Ok(raw_bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_util::metadata;
+    use crate::{Channels, DecodeLimits, Limit, Pixel, Strictness};
+
+    use super::*;
+
+    #[test]
+    fn transfer_function() {
+        assert_eq!(srgb_to_linear(0.0), 0.0);
+        assert!((srgb_to_linear(1.0) - 1.0).abs() < 1e-6);
+        assert!((srgb_to_linear(0.5) - 0.21404).abs() < 1e-4);
+        assert!((linear_to_srgb(0.21404) - 0.5).abs() < 1e-4);
+        assert!((linear_to_srgb(0.002) - 0.02584).abs() < 1e-5);
+    }
+
+    #[test]
+    fn every_value_round_trips() {
+        for colorspace in [Colorspace::SrgbLinearAlpha, Colorspace::AllLinearAlpha] {
+            let metadata = ImgMetadata { colorspace, ..metadata(256, 1, Channels::RGBA) };
+            let pixels: Vec<u8> = (0..=255).flat_map(|value| [value, 255 - value, value, value]).collect();
+            let qoi = crate::encode(&pixels, &metadata);
+
+            let (_, linear) = decode_linear(&qoi).unwrap();
+            assert_eq!(encode_linear(&linear, &metadata).unwrap(), qoi);
+        }
+    }
+
+    #[test]
+    fn decode_uses_header_colorspace() {
+        let pixels = [128, 128, 128, 128];
+
+        let qoi = crate::encode(&pixels, &metadata(1, 1, Channels::RGBA));
+        let (_, linear) = decode_linear(&qoi).unwrap();
+        assert!((linear[0] - 0.21586).abs() < 1e-4);
+        assert_eq!(linear[3], 128.0 / 255.0);
+
+        let qoi = crate::encode(&pixels, &ImgMetadata { colorspace: Colorspace::AllLinearAlpha, ..metadata(1, 1, Channels::RGBA) });
+        let (_, linear) = decode_linear(&qoi).unwrap();
+        assert_eq!(linear, [128.0 / 255.0; 4]);
+
+        let qoi = crate::encode(&pixels[..3], &ImgMetadata { colorspace: Colorspace::AllLinearAlpha, ..metadata(1, 1, Channels::RGB) });
+        let (_, linear) = decode_linear(&qoi).unwrap();
+        assert_eq!(linear[3], 1.0);
+    }
+
+    #[test]
+    fn decode_options_apply() {
+        let metadata = ImgMetadata { colorspace: Colorspace::AllLinearAlpha, ..metadata(2, 1, Channels::RGBA) };
+        let qoi = crate::encode(&[255, 0, 0, 51, 0, 0, 255, 255], &metadata);
+
+        let limits = DecodeLimits { max_width: 1, ..DecodeLimits::default() };
+        assert_eq!(DecodeOptions::new().with_limits(limits).decode_linear(&qoi).err(), Some(QoiError::LimitExceeded { offset: 4, width: 2, height: 1, limit: Limit::MaxWidth(1) }));
+
+        //the 8 bit pixels alone would fit, but not together with the f32 values
+        let limits = DecodeLimits { max_alloc_bytes: 39, ..DecodeLimits::default() };
+        assert!(DecodeOptions::new().with_limits(limits).decode(&qoi).is_ok());
+        assert_eq!(DecodeOptions::new().with_limits(limits).decode_linear(&qoi).err(), Some(QoiError::LimitExceeded { offset: 4, width: 2, height: 1, limit: Limit::MaxAllocBytes(39) }));
+        assert!(DecodeOptions::new().with_limits(DecodeLimits { max_alloc_bytes: 40, ..limits }).decode_linear(&qoi).is_ok());
+
+        //the missing end marker is only an error in strict mode, and the layout and premultiplication are overridden
+        let truncated = &qoi[..qoi.len() - 8];
+        assert!(decode_linear(truncated).is_err());
+        let options = DecodeOptions::new()
+            .with_strictness(Strictness::Lenient { fill: Pixel::rgb(0, 0, 0) })
+            .with_layout(PixelLayout::BGR)
+            .with_premultiplied_alpha(true);
+        assert_eq!(options.decode_linear(truncated).unwrap().1, [1.0, 0.0, 0.0, 0.2, 0.0, 0.0, 1.0, 1.0]);
+    }
+
+    #[test]
+    fn encode_clamps_and_converts() {
+        let metadata = metadata(2, 1, Channels::RGB);
+        let qoi = encode_linear(&[0.21586, -1.0, 2.0, 0.0, f32::NAN, 1.0], &metadata).unwrap();
+        let (_, decoded) = crate::try_decode(&qoi).unwrap();
+        assert_eq!(decoded, [128, 0, 255, 0, 0, 255]);
+
+        assert_eq!(encode_linear(&[0.5; 5], &metadata), Err(EncodeError::BufferSizeMismatch { expected: 6, actual: 5 }));
+    }
+}
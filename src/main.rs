@@ -1,9 +1,11 @@
-use std::{env, fs};
+use std::env;
+use std::fs::File;
+use std::io::BufWriter;
 use std::process::exit;
 
 use image::ImageFormat;
 
-use jaqoi::Channels;
+use jaqoi::{Channels, Colorspace, PixelLayout};
 
 struct Config<'a> {
     input_file_name: &'a str,
@@ -13,9 +15,9 @@ struct Config<'a> {
 }
 
 impl<'a> Config<'a> {
-    fn build(arguments: &'a Vec<String>) -> Result<Config<'a>, &'static str> {
+    fn build(arguments: &'a [String]) -> Result<Config<'a>, &'static str> {
         if arguments.len() < 3 {
-            return Err("Need at least 2 arguments for input filepath and output filepath")
+            return Err("Need at least 2 arguments for input filepath and output filepath, or \"info\" followed by QOI filepaths")
         }
         let input_file_name = &arguments[1];
         let output_file_name = &arguments[2];
@@ -40,9 +42,64 @@ impl<'a> Config<'a> {
     }
 }
 
+/// Prints the header information of each QOI file, returning whether all of them could be read.
+fn print_info(file_names: &[String]) -> bool {
+    let mut all_read = true;
+
+    for file_name in file_names {
+        let info = File::open(file_name)
+            .map_err(|err| err.to_string())
+            .and_then(|mut file| {
+                let file_size = file.metadata().map_err(|err| err.to_string())?.len();
+                let metadata = jaqoi::read_metadata_from(&mut file).map_err(|err| err.to_string())?;
+                Ok((metadata, file_size))
+            });
+
+        match info {
+            Ok((metadata, file_size)) => {
+                let (channels, channel_names) = match metadata.channels {
+                    Channels::RGB => {(3, "RGB")}
+                    Channels::RGBA => {(4, "RGBA")}
+                };
+                let colorspace = match metadata.colorspace {
+                    Colorspace::SrgbLinearAlpha => {"sRGB with linear alpha"}
+                    Colorspace::AllLinearAlpha => {"all channels linear"}
+                };
+                let bits_per_pixel = file_size as f64 * 8.0 / (metadata.width as f64 * metadata.height as f64);
+
+                println!("{}: {}x{}, {} channels ({}), {}, {} bytes, {:.2} bits per pixel",
+                         file_name, metadata.width, metadata.height, channels, channel_names, colorspace, file_size, bits_per_pixel);
+            }
+            Err(err) => {
+                println!("{}: {}", file_name, err);
+                all_read = false;
+            }
+        }
+    }
+
+    all_read
+}
+
+/// Converts images that only hold gray pixels to 8 bit gray, so masks decoded from QOI aren't saved as RGB.
+fn collapse_grayscale(img: image::DynamicImage) -> image::DynamicImage {
+    match img.color() {
+        image::ColorType::Rgb8 if jaqoi::is_grayscale(img.as_bytes(), PixelLayout::RGB) => {image::DynamicImage::from(img.to_luma8())}
+        image::ColorType::Rgba8 if jaqoi::is_grayscale(img.as_bytes(), PixelLayout::RGBA) => {image::DynamicImage::from(img.to_luma_alpha8())}
+        _ => {img}
+    }
+}
+
 fn main() {
     let args: Vec<String> = env::args().collect();
 
+    if args.len() > 1 && args[1] == "info" {
+        if args.len() < 3 {
+            println!("Need at least 1 QOI filepath after \"info\"");
+            exit(1);
+        }
+        exit(if print_info(&args[2..]) {0} else {1});
+    }
+
     let config = match Config::build(&args) {
         Ok(config) => config,
         Err(err) => {
@@ -51,45 +108,39 @@ fn main() {
         }
     };
 
-    let img;
-
-    if ImageFormat::Qoi == config.input_image_format {
-        let (metadata, decoded_raw) = jaqoi::decode(&fs::read(config.input_file_name).unwrap().to_vec());
-        img = match metadata.channels{
-            Channels::RGB => {image::DynamicImage::from(image::RgbImage::from_raw(metadata.width, metadata.height, decoded_raw).unwrap())}
-            Channels::RGBA => {image::DynamicImage::from(image::RgbaImage::from_raw(metadata.width, metadata.height, decoded_raw).unwrap())}
+    let img = if ImageFormat::Qoi == config.input_image_format {
+        let decoded = File::open(config.input_file_name)
+            .map_err(image::ImageError::IoError)
+            .and_then(|file| Ok(jaqoi::QoiDecoder::new(file)?))
+            .and_then(image::DynamicImage::from_decoder);
+        match decoded {
+            Ok(img) => img,
+            Err(err) => {
+                println!("Error decoding {}: {}", config.input_file_name, err);
+                exit(1);
+            }
         }
     } else {
-        img = image::open(config.input_file_name).unwrap();
-    }
+        image::open(config.input_file_name).unwrap()
+    };
 
     if ImageFormat::Qoi == config.output_image_format {
-        let metadata;
-        let bytes;
-        match img.color() {
-            image::ColorType::Rgba8 => {
-                metadata = jaqoi::ImgMetadata {
-                    width: img.width(),
-                    height: img.height(),
-                    channels: Channels::RGBA,
-                    colorspace: jaqoi::Colorspace::SrgbLinearAlpha,
-                };
-                bytes = img.as_rgba8().unwrap().as_raw();
-            },
-            _ => {
-                metadata = jaqoi::ImgMetadata {
-                    width: img.width(),
-                    height: img.height(),
-                    channels: Channels::RGB,
-                    colorspace: jaqoi::Colorspace::SrgbLinearAlpha,
-                };
-                bytes = img.as_rgb8().unwrap().as_raw();
-            }
+        //QOI only stores 8 bit RGB and RGBA, 8 bit gray is expanded by the encoder
+        let img = match img.color() {
+            image::ColorType::Rgb8 | image::ColorType::Rgba8 | image::ColorType::L8 | image::ColorType::La8 => {img}
+            color if color.has_alpha() => {image::DynamicImage::from(img.to_rgba8())}
+            _ => {image::DynamicImage::from(img.to_rgb8())}
+        };
+        let file = File::create(config.output_file_name).expect("Error writing output file");
+        if let Err(err) = img.write_with_encoder(jaqoi::QoiImageEncoder::new(BufWriter::new(file))) {
+            println!("Error encoding {}: {}", config.input_file_name, err);
+            exit(1);
         }
-        // println!("Bytes: {:?}", bytes);
-        let output_raw = jaqoi::encode(bytes, &metadata);
-        fs::write(config.output_file_name, output_raw).expect("Error writing output file");
     } else {
+        let img = match config.input_image_format {
+            ImageFormat::Qoi => {collapse_grayscale(img)}
+            _ => {img}
+        };
         img.save(config.output_file_name).expect("Error writing output file");
     }
 
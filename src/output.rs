@@ -0,0 +1,118 @@
+#[cfg(feature = "alloc")]
+use alloc::vec::Vec;
+
+/// Somewhere the encoder and decoder can write bytes to, so the same code serves growable and fixed size buffers.
+pub(crate) trait Output {
+    fn push(&mut self, byte: u8);
+
+    fn extend_from_slice(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.push(*byte);
+        }
+    }
+
+    /// Number of bytes pushed so far.
+    fn len(&self) -> usize;
+
+    /// Makes room for at least `additional` more bytes, if the output can grow.
+    fn reserve(&mut self, _additional: usize) {}
+}
+
+#[cfg(feature = "alloc")]
+impl Output for Vec<u8> {
+    fn push(&mut self, byte: u8) {
+        Vec::push(self, byte);
+    }
+
+    fn extend_from_slice(&mut self, bytes: &[u8]) {
+        Vec::extend_from_slice(self, bytes);
+    }
+
+    fn len(&self) -> usize {
+        Vec::len(self)
+    }
+
+    fn reserve(&mut self, additional: usize) {
+        Vec::reserve(self, additional);
+    }
+}
+
+/// Writes into a caller provided slice without allocating.
+///
+/// Bytes that don't fit are dropped but still counted, so the required size can be reported afterwards.
+pub(crate) struct SliceOutput<'a> {
+    buf: &'a mut [u8],
+    len: usize,
+}
+
+impl<'a> SliceOutput<'a> {
+    pub(crate) fn new(buf: &'a mut [u8]) -> SliceOutput<'a> {
+        SliceOutput { buf, len: 0 }
+    }
+
+    pub(crate) fn overflowed(&self) -> bool {
+        self.len > self.buf.len()
+    }
+}
+
+impl Output for SliceOutput<'_> {
+    fn push(&mut self, byte: u8) {
+        if let Some(slot) = self.buf.get_mut(self.len) {
+            *slot = byte;
+        }
+        self.len += 1;
+    }
+
+    fn extend_from_slice(&mut self, bytes: &[u8]) {
+        let start = self.len.min(self.buf.len());
+        let fits = bytes.len().min(self.buf.len() - start);
+        self.buf[start..start + fits].copy_from_slice(&bytes[..fits]);
+        self.len += bytes.len();
+    }
+
+    fn len(&self) -> usize {
+        self.len
+    }
+}
+
+#[cfg(all(test, feature = "alloc"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn slice_output_fits() {
+        let mut buf = [0; 3];
+        let mut output = SliceOutput::new(&mut buf);
+        output.push(1);
+        output.push(2);
+
+        assert_eq!(output.len(), 2);
+        assert!(!output.overflowed());
+        assert_eq!(buf, [1, 2, 0]);
+    }
+
+    #[test]
+    fn slice_output_overflow() {
+        let mut buf = [0; 2];
+        let mut output = SliceOutput::new(&mut buf);
+        for byte in 1..=5 {
+            output.push(byte);
+        }
+
+        assert_eq!(output.len(), 5);
+        assert!(output.overflowed());
+        assert_eq!(buf, [1, 2]);
+    }
+    #[test]
+    fn slice_output_extend_overflow() {
+        let mut buf = [0; 3];
+        let mut output = SliceOutput::new(&mut buf);
+        output.extend_from_slice(&[1, 2]);
+        output.extend_from_slice(&[3, 4]);
+        output.extend_from_slice(&[5]);
+
+        assert_eq!(output.len(), 5);
+        assert!(output.overflowed());
+        assert_eq!(buf, [1, 2, 3]);
+    }
+}
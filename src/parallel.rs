@@ -0,0 +1,194 @@
+//! Encoding and decoding on several threads.
+//!
+//! The encoder splits the image into strips that each restart the encoder state, so the file is valid for any decoder.
+//! The decoder needs a [`SeekTable`] to know where each thread can start.
+
+use std::{mem, panic};
+use std::thread;
+
+use crate::decoder::{decode_segment, decoded_len, read_metadata, verify_ending, DecodeLimits, QoiError};
+use crate::encoder::{self, EncodeError, EncoderState};
+use crate::layout::{PixelFormat, PixelLayout};
+use crate::seek::SeekTable;
+use crate::{Channels, ImgMetadata, END_MARKER, HEADER_SIZE};
+
+/// Encodes pixels stored in `layout` as a QOI file, splitting the rows into `strips` horizontal strips that are each
+/// encoded on their own thread.
+///
+/// Every strip after the first starts with a literal of its first pixel and builds its own index, so its chunks don't
+/// depend on the strips above it and any QOI decoder reads the joined file. Runs are cut at strip boundaries, so the
+/// output is a few bytes per strip larger than [`crate::try_encode_with_layout`] gives, and is only identical to it
+/// for a single strip. `strips` is clamped to between 1 and the image height.
+pub fn try_encode_parallel(pixels: &[u8], layout: PixelLayout, metadata: &ImgMetadata, strips: usize) -> Result<Vec<u8>, EncodeError> {
+    encoder::validate(pixels, &layout, metadata)?;
+
+    let format = PixelFormat::from(layout);
+    let alpha_included = metadata.channels == Channels::RGBA;
+    let height = metadata.height as usize;
+    let strips = strips.clamp(1, height);
+    let row_bytes = metadata.width as usize * format.bytes_per_pixel();
+    let max_row_len = metadata.width as usize * (encoder::channels_per_pixel(metadata) + 1);
+
+    let encoded: Vec<Vec<u8>> = thread::scope(|scope| {
+        let handles: Vec<_> = (0..strips).map(|strip| {
+            let rows = strip * height / strips..(strip + 1) * height / strips;
+            let pixels = &pixels[rows.start * row_bytes..rows.end * row_bytes];
+            let format = &format;
+
+            scope.spawn(move || {
+                let mut state = if strip == 0 {
+                    EncoderState::new()
+                } else {
+                    let mut first = format.read_pixel(pixels);
+                    if !alpha_included {
+                        first.a = 255;
+                    }
+                    EncoderState::resynchronized(first, alpha_included)
+                };
+
+                let mut bytes = Vec::with_capacity(rows.len() * max_row_len);
+                state.add_pixels(&mut bytes, pixels, format, alpha_included);
+                state.flush_run(&mut bytes);
+                bytes
+            })
+        }).collect();
+
+        handles.into_iter().map(|handle| handle.join().unwrap_or_else(|payload| panic::resume_unwind(payload))).collect()
+    });
+
+    let mut bytes = Vec::with_capacity(HEADER_SIZE + encoded.iter().map(Vec::len).sum::<usize>() + END_MARKER.len());
+    encoder::add_header(&mut bytes, metadata);
+    for strip in &encoded {
+        bytes.extend_from_slice(strip);
+    }
+    encoder::add_end_marker(&mut bytes);
+
+    Ok(bytes)
+}
+
+/// Decodes a QOI file on `threads` threads, each starting from an entry of `table` and decoding the rows up to the
+/// entry the next thread starts from.
+///
+/// Pixels are written as in [`crate::decode_with_layout`], checked against `limits`. Where each thread's rows end, its
+/// decoder state is checked against the entry the next thread started from, so a table built for a different file is
+/// an error rather than wrong pixels. `threads` is clamped to between 1 and the number of entries.
+pub fn decode_parallel(bytes: &[u8], table: &SeekTable, layout: PixelLayout, limits: &DecodeLimits, threads: usize) -> Result<(ImgMetadata, Vec<u8>), QoiError> {
+    let metadata = read_metadata(bytes)?;
+    limits.check(&metadata, &layout)?;
+    table.check(&metadata)?;
+
+    let format = PixelFormat::from(layout);
+    let entries = &table.entries;
+    let threads = threads.clamp(1, entries.len());
+    let height = metadata.height as usize;
+    let rows_per_entry = table.rows_per_entry as usize;
+    let row_bytes = metadata.width as usize * format.bytes_per_pixel();
+    let mut decoded = vec![0; decoded_len(&metadata, &layout)];
+
+    thread::scope(|scope| {
+        let mut undecoded = &mut decoded[..];
+        let handles: Vec<_> = (0..threads).map(|thread| {
+            let first = thread * entries.len() / threads;
+            let next = (thread + 1) * entries.len() / threads;
+            let rows = first * rows_per_entry..next.saturating_mul(rows_per_entry).min(height);
+            let (out, rest) = mem::take(&mut undecoded).split_at_mut(rows.len() * row_bytes);
+            undecoded = rest;
+            let format = &format;
+
+            scope.spawn(move || {
+                let entry = &entries[first];
+                let mut state = entry.state.clone();
+                let chunks_end = decode_segment(bytes, entry.offset, entry.skip, &mut state, out, format, rows.end < height)?;
+
+                match entries.get(next) {
+                    Some(next) => {next.check_reached(chunks_end, &state)}
+                    None => {verify_ending(bytes, chunks_end)}
+                }
+            })
+        }).collect();
+
+        handles.into_iter().try_for_each(|handle| handle.join().unwrap_or_else(|payload| panic::resume_unwind(payload)))
+    })?;
+
+    Ok((metadata, decoded))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{metadata, pixels};
+
+    #[test]
+    fn single_strip_matches_sequential() {
+        let pixels = pixels(13, 11);
+        for channels in [Channels::RGB, Channels::RGBA] {
+            let metadata = metadata(13, 11, channels);
+            let sequential = crate::try_encode_with_layout(&pixels, PixelLayout::RGBA, &metadata).unwrap();
+            assert_eq!(try_encode_parallel(&pixels, PixelLayout::RGBA, &metadata, 1).unwrap(), sequential);
+            assert_eq!(try_encode_parallel(&pixels, PixelLayout::RGBA, &metadata, 0).unwrap(), sequential);
+        }
+    }
+
+    #[test]
+    fn strips_decode_to_same_pixels() {
+        let pixels = pixels(13, 11);
+        for channels in [Channels::RGB, Channels::RGBA] {
+            let metadata = metadata(13, 11, channels);
+            let expected = crate::try_decode(&crate::try_encode_with_layout(&pixels, PixelLayout::RGBA, &metadata).unwrap()).unwrap();
+
+            for strips in [2, 3, 7, 11, 100] {
+                let encoded = try_encode_parallel(&pixels, PixelLayout::RGBA, &metadata, strips).unwrap();
+                assert!(encoded.len() <= encoder::max_encoded_len(&metadata));
+                assert_eq!(crate::try_decode(&encoded).unwrap(), expected, "{strips} strips of {:?}", metadata.channels);
+            }
+        }
+    }
+
+    #[test]
+    fn parallel_decode_matches_sequential() {
+        let pixels = pixels(13, 11);
+        let metadata = metadata(13, 11, Channels::RGBA);
+        let encoded = crate::try_encode_with_layout(&pixels, PixelLayout::RGBA, &metadata).unwrap();
+        let (_, expected) = crate::decode_with_layout(&encoded, PixelLayout::BGR).unwrap();
+
+        for rows_per_entry in [1, 2, 5, 11] {
+            let table = SeekTable::build(&encoded, rows_per_entry).unwrap();
+            for threads in [0, 1, 2, 3, 100] {
+                let (decoded_metadata, decoded) = decode_parallel(&encoded, &table, PixelLayout::BGR, &DecodeLimits::default(), threads).unwrap();
+                assert_eq!(decoded_metadata, metadata);
+                assert_eq!(decoded, expected, "{threads} threads, entries every {rows_per_entry} rows");
+            }
+        }
+    }
+
+    #[test]
+    fn parallel_decode_rejects_other_tables() {
+        let metadata = metadata(13, 11, Channels::RGBA);
+        let encoded = crate::try_encode_with_layout(&pixels(13, 11), PixelLayout::RGBA, &metadata).unwrap();
+
+        //same dimensions, different chunks
+        let mut other_pixels = pixels(13, 11);
+        other_pixels[40] ^= 1;
+        let other = crate::try_encode_with_layout(&other_pixels, PixelLayout::RGBA, &metadata).unwrap();
+        let table = SeekTable::build(&other, 3).unwrap();
+
+        assert!(matches!(decode_parallel(&encoded, &table, PixelLayout::RGBA, &DecodeLimits::default(), 4), Err(QoiError::InvalidSeekTable { .. })));
+    }
+
+    #[test]
+    fn parallel_decode_applies_limits() {
+        let metadata = metadata(13, 11, Channels::RGBA);
+        let encoded = crate::try_encode_with_layout(&pixels(13, 11), PixelLayout::RGBA, &metadata).unwrap();
+        let table = SeekTable::build(&encoded, 3).unwrap();
+
+        let limits = DecodeLimits { max_pixels: 142, ..DecodeLimits::default() };
+        assert!(matches!(decode_parallel(&encoded, &table, PixelLayout::RGBA, &limits, 2), Err(QoiError::LimitExceeded { .. })));
+    }
+
+    #[test]
+    fn invalid_input_is_rejected() {
+        let metadata = metadata(4, 4, Channels::RGBA);
+        assert_eq!(try_encode_parallel(&[0; 60], PixelLayout::RGBA, &metadata, 2), Err(EncodeError::BufferSizeMismatch { expected: 64, actual: 60 }));
+        assert_eq!(try_encode_parallel(&[], PixelLayout::RGBA, &ImgMetadata { height: 0, ..metadata }, 2), Err(EncodeError::ZeroDimensions { width: 4, height: 0 }));
+    }
+}
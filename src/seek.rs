@@ -0,0 +1,431 @@
+//! Seek tables, which save the decoder state every few rows so decoding can start part way through a file.
+//!
+//! A serialized table is a list of entries followed by a footer, all big endian like the QOI header:
+//!
+//! | bytes | per entry |
+//! |-------|-----------|
+//! | 8 | offset of the chunk holding the row's first pixel |
+//! | 1 | pixels of that chunk, always a run when not 0, that belong to earlier rows |
+//! | 4 | the previous pixel before the chunk, as RGBA |
+//! | 256 | the 64 index entries before the chunk, as RGBA |
+//!
+//! The footer is the number of rows between entries and the number of entries as `u32`s, then `qsek`. Keeping the
+//! magic last means a table appended after a file's end marker can be found from the end of the file.
+
+#[cfg(feature = "alloc")]
+use alloc::{vec, vec::Vec};
+#[cfg(feature = "alloc")]
+use core::ops::Range;
+
+#[cfg(feature = "alloc")]
+use crate::decoder::{decode_segment, operation_payload_len, parse_operation, read_metadata, run_length, verify_ending, DecodeLimits};
+use crate::decoder::{DecoderState, QoiError};
+#[cfg(feature = "alloc")]
+use crate::encoder::{calculate_index, EncodeError};
+#[cfg(feature = "alloc")]
+use crate::layout::{PixelFormat, PixelLayout};
+#[cfg(feature = "alloc")]
+use crate::output::SliceOutput;
+#[cfg(feature = "alloc")]
+use crate::{Operation, END_MARKER};
+use crate::{ImgMetadata, Pixel, HEADER_SIZE};
+
+const MAGIC: [u8; 4] = *b"qsek";
+const ENTRY_LEN: usize = 8 + 1 + 4 + 64 * 4;
+const FOOTER_LEN: usize = 4 + 4 + MAGIC.len();
+
+/// Checks that `bytes` is exactly one serialized seek table that fits the image in `metadata`, as found after the end
+/// marker of a file it was appended to. Nothing is allocated.
+pub(crate) fn check_trailer(bytes: &[u8], metadata: &ImgMetadata) -> Result<(), QoiError> {
+    let rows_per_entry = parse_footer(bytes)?;
+    let entries = bytes[..bytes.len() - FOOTER_LEN].chunks_exact(ENTRY_LEN);
+    check_entries(rows_per_entry, entries.len(), &parse_entry(&bytes[..ENTRY_LEN], 0)?, metadata)?;
+
+    for (i, entry) in entries.enumerate() {
+        parse_entry(entry, i)?;
+    }
+    Ok(())
+}
+
+/// Length of the serialized seek table ending `bytes`, as given by its footer.
+fn table_len(bytes: &[u8]) -> Option<usize> {
+    let footer = &bytes[bytes.len().checked_sub(FOOTER_LEN)?..];
+    if footer[8..] != MAGIC {
+        return None;
+    }
+
+    let entries = u32::from_be_bytes(footer[4..8].try_into().unwrap()) as usize;
+    entries.checked_mul(ENTRY_LEN)?.checked_add(FOOTER_LEN)
+}
+
+/// Checks that `bytes` holds exactly one serialized table with at least one entry, returning its rows per entry.
+fn parse_footer(bytes: &[u8]) -> Result<u32, QoiError> {
+    if table_len(bytes) != Some(bytes.len()) {
+        return Err(QoiError::InvalidSeekTable { offset: 0 });
+    }
+
+    let footer = &bytes[bytes.len() - FOOTER_LEN..];
+    let rows_per_entry = u32::from_be_bytes(footer[..4].try_into().unwrap());
+    if rows_per_entry == 0 || bytes.len() == FOOTER_LEN {
+        return Err(QoiError::InvalidSeekTable { offset: bytes.len() - FOOTER_LEN });
+    }
+    Ok(rows_per_entry)
+}
+
+/// Reads the `i`th serialized entry, which is `entry`.
+fn parse_entry(entry: &[u8], i: usize) -> Result<SeekEntry, QoiError> {
+    let offset = u64::from_be_bytes(entry[..8].try_into().unwrap());
+    let skip = entry[8] as usize;
+    //no run is long enough to leave 62 pixels for earlier rows
+    if skip >= 62 {
+        return Err(QoiError::InvalidSeekTable { offset: i * ENTRY_LEN + 8 });
+    }
+
+    let mut state = DecoderState::new();
+    state.prev_pixel = Pixel::from(<[u8; 4]>::try_from(&entry[9..13]).unwrap());
+    for (slot, values) in state.index.iter_mut().zip(entry[13..].chunks_exact(4)) {
+        *slot = Pixel::new(values[0], values[1], values[2], values[3]);
+    }
+
+    let offset = usize::try_from(offset).map_err(|_| QoiError::InvalidSeekTable { offset: i * ENTRY_LEN })?;
+    Ok(SeekEntry { offset, skip, state })
+}
+
+/// Checks that a table of `entries` entries, starting with `first`, has one entry for every `rows_per_entry` rows of
+/// the image in `metadata` and starts at the first row with the decoder's initial state.
+fn check_entries(rows_per_entry: u32, entries: usize, first: &SeekEntry, metadata: &ImgMetadata) -> Result<(), QoiError> {
+    let initial = SeekEntry { offset: HEADER_SIZE, skip: 0, state: DecoderState::new() };
+    if entries != metadata.height.div_ceil(rows_per_entry) as usize || *first != initial {
+        return Err(QoiError::InvalidSeekTable { offset: HEADER_SIZE });
+    }
+    Ok(())
+}
+
+/// Byte offsets and decoder state saved every few rows of a QOI file, so decoding can start at any of those rows.
+///
+/// Build one with [`SeekTable::build`] or [`try_encode_with_seek_table`], then keep the bytes from
+/// [`SeekTable::to_bytes`] in a sidecar file or append them to the QOI file. Decoders stop reading at the end marker
+/// and this crate accepts a seek table after it, so appending it keeps the file readable.
+#[cfg(feature = "alloc")]
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct SeekTable {
+    pub(crate) rows_per_entry: u32,
+    pub(crate) entries: Vec<SeekEntry>,
+}
+
+/// Where a row starts: the chunk holding its first pixel, how many of that chunk's pixels belong to earlier rows and
+/// the decoder state before the chunk.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub(crate) struct SeekEntry {
+    pub(crate) offset: usize,
+    pub(crate) skip: usize,
+    pub(crate) state: DecoderState,
+}
+
+#[cfg(feature = "alloc")]
+impl SeekEntry {
+    /// Checks that decoding the rows above this entry stopped where it starts, at `chunks_end` with `state`.
+    pub(crate) fn check_reached(&self, chunks_end: usize, state: &DecoderState) -> Result<(), QoiError> {
+        //a run cut short at the end of the rows has been read and its pixel indexed, unlike in the saved state
+        let mut expected = self.state.clone();
+        let mut expected_end = self.offset;
+        if self.skip > 0 {
+            expected.index[calculate_index(&expected.prev_pixel)] = expected.prev_pixel;
+            expected_end += 1;
+        }
+        if chunks_end != expected_end || *state != expected {
+            return Err(QoiError::InvalidSeekTable { offset: self.offset });
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl SeekTable {
+    /// Reads through the chunks of a QOI file, saving an entry for every `rows_per_entry` rows starting at the first.
+    ///
+    /// `rows_per_entry` is at least 1. The file is checked as strictly as [`crate::try_decode`] does, but no pixels are
+    /// written, so this is faster than decoding it.
+    pub fn build(bytes: &[u8], rows_per_entry: u32) -> Result<SeekTable, QoiError> {
+        let metadata = read_metadata(bytes)?;
+        let rows_per_entry = rows_per_entry.max(1);
+        let width = metadata.width as usize;
+        let total_pixels = width * metadata.height as usize;
+
+        //chunks are written somewhere so the decoder's state moves on, only the state is kept
+        let format = PixelFormat::from(PixelLayout::L8);
+        let mut scratch = [0; 62];
+
+        let mut entries = Vec::new();
+        let mut state = DecoderState::new();
+        let mut offset = HEADER_SIZE;
+        let mut pixels_seen = 0;
+        let mut next_entry = 0;
+
+        while pixels_seen < total_pixels {
+            let chunks = &bytes[offset..];
+            if chunks == END_MARKER {
+                return Err(QoiError::PixelCountMismatch { offset, expected: total_pixels, actual: pixels_seen });
+            }
+            let tag = *chunks.first().ok_or(QoiError::Truncated { offset })?;
+            let operation = parse_operation(&tag);
+            if chunks.len() - 1 < operation_payload_len(&operation) {
+                return Err(QoiError::Truncated { offset });
+            }
+
+            let pixels = if operation == Operation::QoiOpRun {run_length(&tag)} else {1};
+            if pixels_seen + pixels > total_pixels {
+                return Err(QoiError::PixelCountMismatch { offset, expected: total_pixels, actual: pixels_seen + pixels });
+            }
+            while next_entry < pixels_seen + pixels {
+                entries.push(SeekEntry { offset, skip: next_entry - pixels_seen, state: state.clone() });
+                next_entry += rows_per_entry as usize * width;
+            }
+
+            let mut iter = chunks[1..].iter();
+            pixels_seen += state.write_chunk(&mut SliceOutput::new(&mut scratch), &tag, &mut iter, &format);
+            offset = bytes.len() - iter.len();
+        }
+        verify_ending(bytes, offset)?;
+
+        Ok(SeekTable { rows_per_entry, entries })
+    }
+
+    /// Reads a table serialized by [`SeekTable::to_bytes`], such as a sidecar file. `bytes` must hold only the table.
+    pub fn from_bytes(bytes: &[u8]) -> Result<SeekTable, QoiError> {
+        let rows_per_entry = parse_footer(bytes)?;
+        let entries = bytes[..bytes.len() - FOOTER_LEN].chunks_exact(ENTRY_LEN).enumerate()
+            .map(|(i, entry)| parse_entry(entry, i))
+            .collect::<Result<Vec<SeekEntry>, QoiError>>()?;
+
+        Ok(SeekTable { rows_per_entry, entries })
+    }
+
+    /// Reads the table appended after the end marker of `file`.
+    pub fn from_trailer(file: &[u8]) -> Result<SeekTable, QoiError> {
+        let table_len = table_len(file).filter(|len| *len <= file.len()).ok_or(QoiError::InvalidSeekTable { offset: file.len() })?;
+        SeekTable::from_bytes(&file[file.len() - table_len..])
+    }
+
+    /// The table in the format described in this module, for writing to a sidecar file or appending to the QOI file.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(self.entries.len() * ENTRY_LEN + FOOTER_LEN);
+        for entry in &self.entries {
+            bytes.extend_from_slice(&(entry.offset as u64).to_be_bytes());
+            bytes.push(entry.skip as u8);
+            bytes.extend_from_slice(&<[u8; 4]>::from(entry.state.prev_pixel));
+            for pixel in &entry.state.index {
+                bytes.extend_from_slice(&<[u8; 4]>::from(*pixel));
+            }
+        }
+
+        bytes.extend_from_slice(&self.rows_per_entry.to_be_bytes());
+        bytes.extend_from_slice(&(self.entries.len() as u32).to_be_bytes());
+        bytes.extend_from_slice(&MAGIC);
+        bytes
+    }
+
+    /// Number of rows from one entry to the next.
+    pub fn rows_per_entry(&self) -> u32 {
+        self.rows_per_entry
+    }
+
+    /// Checks that the table has an entry for every `rows_per_entry` rows of the image in `metadata`, starting at the
+    /// first row with the decoder's initial state.
+    pub(crate) fn check(&self, metadata: &ImgMetadata) -> Result<(), QoiError> {
+        check_entries(self.rows_per_entry, self.entries.len(), &self.entries[0], metadata)
+    }
+}
+
+/// Encodes pixels stored in `layout` as in [`crate::try_encode_with_layout`], along with a seek table with an entry
+/// every `rows_per_entry` rows.
+#[cfg(feature = "alloc")]
+pub fn try_encode_with_seek_table(pixels: &[u8], layout: PixelLayout, metadata: &ImgMetadata, rows_per_entry: u32) -> Result<(Vec<u8>, SeekTable), EncodeError> {
+    let encoded = crate::try_encode_with_layout(pixels, layout, metadata)?;
+    //the encoder's output is always a valid file
+    let table = SeekTable::build(&encoded, rows_per_entry).unwrap();
+    Ok((encoded, table))
+}
+
+/// Decodes only `rows` of a QOI file, starting from the last entry of `table` at or above the first of them.
+///
+/// Pixels are written in `layout`, row by row, and rows past the bottom of the image are left out. Only the chunks from
+/// that entry to the next entry below the last row are read, and the decoder state there is checked against that entry,
+/// so a table built for a different file is an error rather than wrong pixels. The end marker is only checked when
+/// there is no entry below the last row.
+#[cfg(feature = "alloc")]
+pub fn decode_rows(bytes: &[u8], table: &SeekTable, rows: Range<u32>, layout: PixelLayout, limits: &DecodeLimits) -> Result<Vec<u8>, QoiError> {
+    let metadata = read_metadata(bytes)?;
+    limits.check(&metadata, &layout)?;
+    table.check(&metadata)?;
+
+    let end = rows.end.min(metadata.height);
+    if rows.start >= end {
+        return Ok(Vec::new());
+    }
+
+    let format = PixelFormat::from(layout);
+    let row_bytes = metadata.width as usize * format.bytes_per_pixel();
+    let entry = &table.entries[(rows.start / table.rows_per_entry) as usize];
+    let entry_row = rows.start - rows.start % table.rows_per_entry;
+    let next = end.div_ceil(table.rows_per_entry) as usize;
+    let next_row = table.rows_per_entry.saturating_mul(next as u32).min(metadata.height);
+
+    let mut decoded = vec![0; (next_row - entry_row) as usize * row_bytes];
+    let mut state = entry.state.clone();
+    let chunks_end = decode_segment(bytes, entry.offset, entry.skip, &mut state, &mut decoded, &format, next_row < metadata.height)?;
+    match table.entries.get(next) {
+        Some(next) => {next.check_reached(chunks_end, &state)?}
+        None => {verify_ending(bytes, chunks_end)?}
+    }
+
+    decoded.truncate((end - entry_row) as usize * row_bytes);
+    decoded.drain(..(rows.start - entry_row) as usize * row_bytes);
+    Ok(decoded)
+}
+
+#[cfg(all(test, feature = "alloc"))]
+mod tests {
+    use super::*;
+    use crate::test_util::{metadata, pixels};
+    use crate::Channels;
+
+    #[test]
+    fn entries_start_inside_runs() {
+        //opaque black is the initial previous pixel, so the whole image is one run
+        let encoded = crate::encode(&[0, 0, 0, 255].repeat(40), &metadata(4, 10, Channels::RGBA));
+        let table = SeekTable::build(&encoded, 3).unwrap();
+
+        assert_eq!(table.rows_per_entry(), 3);
+        let starts: Vec<(usize, usize)> = table.entries.iter().map(|entry| (entry.offset, entry.skip)).collect();
+        assert_eq!(starts, [(14, 0), (14, 12), (14, 24), (14, 36)]);
+    }
+
+    #[test]
+    fn build_rejects_invalid_files() {
+        let mut encoded = crate::encode(&pixels(5, 4), &metadata(5, 4, Channels::RGBA));
+        assert_eq!(SeekTable::build(&encoded, 0).unwrap().rows_per_entry(), 1);
+
+        encoded.push(0);
+        assert!(matches!(SeekTable::build(&encoded, 1), Err(QoiError::TrailingBytes { .. })));
+        encoded.truncate(20);
+        assert!(matches!(SeekTable::build(&encoded, 1), Err(QoiError::Truncated { .. })));
+    }
+
+    #[test]
+    fn serialized_round_trip() {
+        let encoded = crate::encode(&pixels(7, 9), &metadata(7, 9, Channels::RGBA));
+        let table = SeekTable::build(&encoded, 2).unwrap();
+        let bytes = table.to_bytes();
+        assert_eq!(bytes.len(), 5 * ENTRY_LEN + FOOTER_LEN);
+        assert_eq!(SeekTable::from_bytes(&bytes), Ok(table.clone()));
+
+        let mut file = encoded.clone();
+        file.extend_from_slice(&bytes);
+        assert_eq!(SeekTable::from_trailer(&file), Ok(table));
+        assert_eq!(SeekTable::from_trailer(&encoded), Err(QoiError::InvalidSeekTable { offset: encoded.len() }));
+    }
+
+    #[test]
+    fn from_bytes_rejects_malformed_tables() {
+        let encoded = crate::encode(&pixels(7, 9), &metadata(7, 9, Channels::RGBA));
+        let bytes = SeekTable::build(&encoded, 2).unwrap().to_bytes();
+
+        assert_eq!(SeekTable::from_bytes(&bytes[1..]), Err(QoiError::InvalidSeekTable { offset: 0 }));
+        assert_eq!(SeekTable::from_bytes(&bytes[bytes.len() - FOOTER_LEN + 1..]), Err(QoiError::InvalidSeekTable { offset: 0 }));
+        assert_eq!(SeekTable::from_bytes(&[0, 0, 0, 1, 0, 0, 0, 0, b'q', b's', b'e', b'k']), Err(QoiError::InvalidSeekTable { offset: 0 }));
+
+        let mut zero_rows = bytes.clone();
+        let footer = zero_rows.len() - FOOTER_LEN;
+        zero_rows[footer..footer + 4].copy_from_slice(&[0; 4]);
+        assert_eq!(SeekTable::from_bytes(&zero_rows), Err(QoiError::InvalidSeekTable { offset: footer }));
+
+        let mut long_skip = bytes;
+        long_skip[ENTRY_LEN + 8] = 62;
+        assert_eq!(SeekTable::from_bytes(&long_skip), Err(QoiError::InvalidSeekTable { offset: ENTRY_LEN + 8 }));
+    }
+
+    #[test]
+    fn decoding_accepts_appended_table() {
+        let (mut file, table) = try_encode_with_seek_table(&pixels(7, 9), PixelLayout::RGBA, &metadata(7, 9, Channels::RGBA), 4).unwrap();
+        let expected = crate::try_decode(&file).unwrap();
+        file.extend_from_slice(&table.to_bytes());
+        assert_eq!(crate::try_decode(&file), Ok(expected));
+
+        //anything else after the end marker is still rejected
+        file.push(0);
+        assert!(matches!(crate::try_decode(&file), Err(QoiError::TrailingBytes { .. })));
+    }
+
+    #[test]
+    fn decoding_rejects_tables_that_dont_fit() {
+        let (file, table) = try_encode_with_seek_table(&pixels(7, 9), PixelLayout::RGBA, &metadata(7, 9, Channels::RGBA), 4).unwrap();
+        let end = file.len();
+        let trailing = Err(QoiError::TrailingBytes { offset: end });
+
+        //garbage with a footer that gives the right length
+        let mut garbage = file.clone();
+        garbage.extend_from_slice(&[0xAB; ENTRY_LEN]);
+        garbage.extend_from_slice(&[0, 0, 0, 9, 0, 0, 0, 1]);
+        garbage.extend_from_slice(&MAGIC);
+        assert_eq!(crate::try_decode(&garbage), trailing);
+
+        //a well formed table for an image of another height
+        let other = crate::encode(&pixels(7, 20), &metadata(7, 20, Channels::RGBA));
+        let mut wrong_table = file.clone();
+        wrong_table.extend_from_slice(&SeekTable::build(&other, 4).unwrap().to_bytes());
+        assert_eq!(crate::try_decode(&wrong_table), trailing);
+
+        let mut long_skip = table.to_bytes();
+        long_skip[ENTRY_LEN + 8] = 62;
+        let mut file = file;
+        file.extend_from_slice(&long_skip);
+        assert_eq!(crate::try_decode(&file), trailing);
+    }
+
+    #[test]
+    fn decode_rows_matches_full_decode() {
+        let (width, height) = (7, 11);
+        let pixels = pixels(width, height);
+        for rows_per_entry in [1, 2, 3, 11, 20] {
+            let (encoded, table) = try_encode_with_seek_table(&pixels, PixelLayout::RGBA, &metadata(width, height, Channels::RGBA), rows_per_entry).unwrap();
+            let (_, full) = crate::decode_with_layout(&encoded, PixelLayout::BGR).unwrap();
+            let row_bytes = width as usize * 3;
+
+            for start in 0..height {
+                for end in start..=height + 1 {
+                    let decoded = decode_rows(&encoded, &table, start..end, PixelLayout::BGR, &DecodeLimits::default()).unwrap();
+                    let end = end.min(height) as usize;
+                    assert_eq!(decoded, full[start as usize * row_bytes..end * row_bytes], "rows {start}..{end} every {rows_per_entry}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn decode_rows_checks_table_fits_image() {
+        let encoded = crate::encode(&pixels(7, 9), &metadata(7, 9, Channels::RGBA));
+        let other = crate::encode(&pixels(7, 4), &metadata(7, 4, Channels::RGBA));
+        let table = SeekTable::build(&other, 2).unwrap();
+
+        assert_eq!(decode_rows(&encoded, &table, 0..9, PixelLayout::RGBA, &DecodeLimits::default()), Err(QoiError::InvalidSeekTable { offset: HEADER_SIZE }));
+    }
+
+    #[test]
+    fn decode_rows_checks_table_matches_chunks() {
+        let encoded = crate::encode(&pixels(7, 9), &metadata(7, 9, Channels::RGBA));
+        //same dimensions, different chunks
+        let mut other_pixels = pixels(7, 9);
+        other_pixels[40] ^= 1;
+        let table = SeekTable::build(&crate::encode(&other_pixels, &metadata(7, 9, Channels::RGBA)), 2).unwrap();
+
+        assert!(matches!(decode_rows(&encoded, &table, 0..1, PixelLayout::RGBA, &DecodeLimits::default()), Err(QoiError::InvalidSeekTable { .. })));
+    }
+
+    #[test]
+    fn decode_rows_applies_limits() {
+        let (encoded, table) = try_encode_with_seek_table(&pixels(7, 9), PixelLayout::RGBA, &metadata(7, 9, Channels::RGBA), 2).unwrap();
+        let limits = DecodeLimits { max_height: 8, ..DecodeLimits::default() };
+        assert!(matches!(decode_rows(&encoded, &table, 0..1, PixelLayout::RGBA, &limits), Err(QoiError::LimitExceeded { .. })));
+    }
+}
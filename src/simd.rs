@@ -0,0 +1,232 @@
+//! Run detection and run filling many pixels at a time.
+//!
+//! On x86 and x86_64 with `std`, AVX2 or SSE2 is picked at runtime when the CPU supports it. Everything else uses the
+//! scalar versions, which the SIMD versions are tested against.
+
+/// Number of whole pixels after the first in `bytes` that are equal to it, for pixels of 1 to 4 bytes.
+///
+/// Each pixel is compared with the one before it, so the vector versions can compare `bytes` with itself shifted by a
+/// pixel instead of building a vector of repeated pixels.
+pub(crate) fn count_repeats(bytes: &[u8], pixel_len: usize) -> usize {
+    #[cfg(all(feature = "std", any(target_arch = "x86", target_arch = "x86_64")))]
+    {
+        if std::is_x86_feature_detected!("avx2") {
+            //SAFETY: the CPU supports AVX2
+            return unsafe { x86::count_repeats_avx2(bytes, pixel_len) };
+        }
+        if std::is_x86_feature_detected!("sse2") {
+            //SAFETY: the CPU supports SSE2
+            return unsafe { x86::count_repeats_sse2(bytes, pixel_len) };
+        }
+    }
+    count_repeats_scalar(bytes, pixel_len)
+}
+
+fn count_repeats_scalar(bytes: &[u8], pixel_len: usize) -> usize {
+    if bytes.len() < pixel_len {
+        return 0;
+    }
+    let (pixel, rest) = bytes.split_at(pixel_len);
+    rest.chunks_exact(pixel_len).take_while(|values| *values == pixel).count()
+}
+
+/// Fills `out`, which holds whole pixels of `pixel_len` bytes, with copies of its first pixel.
+pub(crate) fn fill_repeats(out: &mut [u8], pixel_len: usize) {
+    #[cfg(all(feature = "std", any(target_arch = "x86", target_arch = "x86_64")))]
+    {
+        //the vector stores only handle 4 byte pixels, which fill a vector exactly
+        if pixel_len == 4 && std::is_x86_feature_detected!("avx2") {
+            //SAFETY: the CPU supports AVX2
+            return unsafe { x86::fill_repeats_4_avx2(out) };
+        }
+        if pixel_len == 4 && std::is_x86_feature_detected!("sse2") {
+            //SAFETY: the CPU supports SSE2
+            return unsafe { x86::fill_repeats_4_sse2(out) };
+        }
+    }
+    fill_repeats_scalar(out, pixel_len)
+}
+
+/// Doubles the filled part of `out` with each copy, so long runs are written with a few large copies.
+fn fill_repeats_scalar(out: &mut [u8], pixel_len: usize) {
+    let mut filled = pixel_len.min(out.len());
+    while filled < out.len() {
+        let copied = filled.min(out.len() - filled);
+        out.copy_within(..copied, filled);
+        filled += copied;
+    }
+}
+
+#[cfg(all(feature = "std", any(target_arch = "x86", target_arch = "x86_64")))]
+mod x86 {
+    #[cfg(target_arch = "x86")]
+    use core::arch::x86::*;
+    #[cfg(target_arch = "x86_64")]
+    use core::arch::x86_64::*;
+
+    use super::{count_repeats_scalar, fill_repeats_scalar};
+
+    #[target_feature(enable = "sse2")]
+    pub(super) unsafe fn count_repeats_sse2(bytes: &[u8], pixel_len: usize) -> usize {
+        let mut offset = 0;
+        while offset + pixel_len + 16 <= bytes.len() {
+            //SAFETY: both reads are 16 bytes inside `bytes`
+            let (previous, values) = unsafe {
+                (_mm_loadu_si128(bytes.as_ptr().add(offset) as *const __m128i), _mm_loadu_si128(bytes.as_ptr().add(offset + pixel_len) as *const __m128i))
+            };
+            let equal = _mm_movemask_epi8(_mm_cmpeq_epi8(previous, values)) as u32;
+            if equal != 0xFFFF {
+                return (offset + equal.trailing_ones() as usize) / pixel_len;
+            }
+            offset += 16;
+        }
+
+        //every pixel that started before `offset` matched
+        let matched = offset / pixel_len;
+        matched + count_repeats_scalar(&bytes[matched * pixel_len..], pixel_len)
+    }
+
+    #[target_feature(enable = "avx2")]
+    pub(super) unsafe fn count_repeats_avx2(bytes: &[u8], pixel_len: usize) -> usize {
+        let mut offset = 0;
+        while offset + pixel_len + 32 <= bytes.len() {
+            //SAFETY: both reads are 32 bytes inside `bytes`
+            let (previous, values) = unsafe {
+                (_mm256_loadu_si256(bytes.as_ptr().add(offset) as *const __m256i), _mm256_loadu_si256(bytes.as_ptr().add(offset + pixel_len) as *const __m256i))
+            };
+            let equal = _mm256_movemask_epi8(_mm256_cmpeq_epi8(previous, values)) as u32;
+            if equal != u32::MAX {
+                return (offset + equal.trailing_ones() as usize) / pixel_len;
+            }
+            offset += 32;
+        }
+
+        //every pixel that started before `offset` matched
+        let matched = offset / pixel_len;
+        matched + count_repeats_scalar(&bytes[matched * pixel_len..], pixel_len)
+    }
+
+    #[target_feature(enable = "sse2")]
+    pub(super) unsafe fn fill_repeats_4_sse2(out: &mut [u8]) {
+        if out.len() < 4 {
+            return;
+        }
+        let pixel = _mm_set1_epi32(i32::from_ne_bytes([out[0], out[1], out[2], out[3]]));
+
+        let mut offset = 0;
+        while offset + 16 <= out.len() {
+            //SAFETY: the write is 16 bytes inside `out`
+            unsafe { _mm_storeu_si128(out.as_mut_ptr().add(offset) as *mut __m128i, pixel) };
+            offset += 16;
+        }
+        fill_repeats_scalar(&mut out[offset.saturating_sub(4)..], 4);
+    }
+
+    #[target_feature(enable = "avx2")]
+    pub(super) unsafe fn fill_repeats_4_avx2(out: &mut [u8]) {
+        if out.len() < 4 {
+            return;
+        }
+        let pixel = _mm256_set1_epi32(i32::from_ne_bytes([out[0], out[1], out[2], out[3]]));
+
+        let mut offset = 0;
+        while offset + 32 <= out.len() {
+            //SAFETY: the write is 32 bytes inside `out`
+            unsafe { _mm256_storeu_si256(out.as_mut_ptr().add(offset) as *mut __m256i, pixel) };
+            offset += 32;
+        }
+        fill_repeats_scalar(&mut out[offset.saturating_sub(4)..], 4);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    type CountRepeats = fn(&[u8], usize) -> usize;
+    type FillRepeats = fn(&mut [u8], usize);
+
+    /// Every version of `count_repeats` this CPU can run, so each is checked whatever the dispatch picks.
+    fn count_repeats_versions() -> Vec<(&'static str, CountRepeats)> {
+        #[allow(unused_mut)]
+        let mut versions: Vec<(&'static str, CountRepeats)> = vec![("dispatch", count_repeats)];
+        #[cfg(all(feature = "std", any(target_arch = "x86", target_arch = "x86_64")))]
+        {
+            if std::is_x86_feature_detected!("sse2") {
+                //SAFETY: the CPU supports SSE2
+                versions.push(("sse2", |bytes, pixel_len| unsafe { x86::count_repeats_sse2(bytes, pixel_len) }));
+            }
+            if std::is_x86_feature_detected!("avx2") {
+                //SAFETY: the CPU supports AVX2
+                versions.push(("avx2", |bytes, pixel_len| unsafe { x86::count_repeats_avx2(bytes, pixel_len) }));
+            }
+        }
+        versions
+    }
+
+    /// Every version of `fill_repeats` this CPU can run.
+    fn fill_repeats_versions() -> Vec<(&'static str, FillRepeats)> {
+        #[allow(unused_mut)]
+        let mut versions: Vec<(&'static str, FillRepeats)> = vec![("dispatch", fill_repeats)];
+        #[cfg(all(feature = "std", any(target_arch = "x86", target_arch = "x86_64")))]
+        {
+            if std::is_x86_feature_detected!("sse2") {
+                //SAFETY: the CPU supports SSE2
+                versions.push(("sse2", |out, pixel_len| if pixel_len == 4 { unsafe { x86::fill_repeats_4_sse2(out) } } else { fill_repeats_scalar(out, pixel_len) }));
+            }
+            if std::is_x86_feature_detected!("avx2") {
+                //SAFETY: the CPU supports AVX2
+                versions.push(("avx2", |out, pixel_len| if pixel_len == 4 { unsafe { x86::fill_repeats_4_avx2(out) } } else { fill_repeats_scalar(out, pixel_len) }));
+            }
+        }
+        versions
+    }
+
+    #[test]
+    fn count_repeats_matches_scalar() {
+        let versions = count_repeats_versions();
+        for pixel_len in 1..=4 {
+            for pixels in [0, 1, 7, 8, 9, 31, 32, 33, 200] {
+                //the pixel to repeat, its repeats, then a partial pixel that is never counted
+                let mut bytes = [7, 8, 9, 10][..pixel_len].repeat(pixels + 2);
+                bytes.pop();
+                for mismatch in 0..bytes.len() {
+                    bytes[mismatch] ^= 0x80;
+                    let expected = (mismatch / pixel_len).saturating_sub(1).min(pixels);
+                    assert_eq!(count_repeats_scalar(&bytes, pixel_len), expected);
+                    for (name, count) in &versions {
+                        assert_eq!(count(&bytes, pixel_len), expected, "{name}, {pixel_len} byte pixels, mismatch at {mismatch}");
+                    }
+                    bytes[mismatch] ^= 0x80;
+                }
+                for (name, count) in &versions {
+                    assert_eq!(count(&bytes, pixel_len), pixels, "{name}, {pixel_len} byte pixels");
+                    assert_eq!(count(&bytes[..pixel_len - 1], pixel_len), 0, "{name}, {pixel_len} byte pixels");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn fill_repeats_matches_scalar() {
+        let versions = fill_repeats_versions();
+        for pixel_len in 1..=4 {
+            let pixel = &[1, 2, 3, 4][..pixel_len];
+            for pixels in 0..70 {
+                let mut expected = vec![0; pixels * pixel_len];
+                if pixels > 0 {
+                    expected[..pixel_len].copy_from_slice(pixel);
+                }
+                let unfilled = expected.clone();
+                fill_repeats_scalar(&mut expected, pixel_len);
+                assert_eq!(expected, pixel.repeat(pixels));
+
+                for (name, fill) in &versions {
+                    let mut filled = unfilled.clone();
+                    fill(&mut filled, pixel_len);
+                    assert_eq!(filled, expected, "{name}, {pixels} pixels of {pixel_len} bytes");
+                }
+            }
+        }
+    }
+}
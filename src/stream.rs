@@ -0,0 +1,501 @@
+use std::io::{BufReader, ErrorKind, Read, Write};
+
+use crate::decoder::{operation_payload_len, parse_metadata, parse_operation, run_length, DecodeLimits, DecoderState, QoiError};
+use crate::encoder::{add_end_marker, add_header, validate_dimensions, EncodeError, EncoderState};
+use crate::layout::{PixelFormat, PixelLayout};
+use crate::output::{Output, SliceOutput};
+use crate::{ImgMetadata, Operation, END_MARKER, HEADER_SIZE};
+
+/// Encodes a QOI image to a writer one row at a time instead of requiring the whole pixel buffer up front.
+///
+/// The header is written by [`QoiEncoder::new`]; each call to [`QoiEncoder::write_row`] then encodes and writes one row.
+/// Runs and the color index carry over between rows, so the output is identical to [`crate::encode`].
+pub struct QoiEncoder<W: Write> {
+    writer: W,
+    layout: PixelLayout,
+    width: u32,
+    height: u32,
+    rows_written: u32,
+    state: EncoderState,
+    buffer: Vec<u8>,
+}
+
+impl<W: Write> QoiEncoder<W> {
+    /// Checks `metadata` and writes the header to `writer`.
+    pub fn new(writer: W, metadata: &ImgMetadata) -> Result<QoiEncoder<W>, EncodeError> {
+        validate_dimensions(metadata)?;
+
+        let mut encoder = QoiEncoder {
+            writer,
+            layout: PixelLayout::from(&metadata.channels),
+            width: metadata.width,
+            height: metadata.height,
+            rows_written: 0,
+            state: EncoderState::new(),
+            buffer: Vec::new(),
+        };
+
+        add_header(&mut encoder.buffer, metadata);
+        encoder.write_buffer()?;
+
+        Ok(encoder)
+    }
+
+    /// Number of bytes [`QoiEncoder::write_row`] expects per row.
+    pub fn row_bytes(&self) -> usize {
+        self.width as usize * self.layout.bytes_per_pixel()
+    }
+
+    /// Encodes the next row of pixels, which must be exactly [`QoiEncoder::row_bytes`] long.
+    pub fn write_row(&mut self, row: &[u8]) -> Result<(), EncodeError> {
+        if row.len() != self.row_bytes() {
+            return Err(EncodeError::BufferSizeMismatch { expected: self.row_bytes(), actual: row.len() });
+        }
+        if self.rows_written == self.height {
+            return Err(EncodeError::PixelCountMismatch { expected: self.total_pixels(), actual: self.total_pixels() + self.width as usize });
+        }
+
+        self.state.add_pixels(&mut self.buffer, row, &PixelFormat::from(self.layout), self.layout.has_alpha());
+        self.rows_written += 1;
+
+        self.write_buffer()
+    }
+
+    /// Writes the pending run and the end marker, returning the underlying writer.
+    ///
+    /// Fails if fewer than `height` rows were written.
+    pub fn finish(mut self) -> Result<W, EncodeError> {
+        if self.rows_written != self.height {
+            return Err(EncodeError::PixelCountMismatch { expected: self.total_pixels(), actual: self.rows_written as usize * self.width as usize });
+        }
+
+        self.state.flush_run(&mut self.buffer);
+        add_end_marker(&mut self.buffer);
+        self.write_buffer()?;
+        self.writer.flush().map_err(|err| EncodeError::Io { kind: err.kind() })?;
+
+        Ok(self.writer)
+    }
+
+    fn total_pixels(&self) -> usize {
+        self.width as usize * self.height as usize
+    }
+
+    fn write_buffer(&mut self) -> Result<(), EncodeError> {
+        self.writer.write_all(&self.buffer).map_err(|err| EncodeError::Io { kind: err.kind() })?;
+        self.buffer.clear();
+        Ok(())
+    }
+}
+
+/// Decodes a QOI image from a reader a few rows at a time instead of holding the whole file and image in memory.
+///
+/// The header is read by [`QoiDecoder::new`]. Pixels are then pulled with [`QoiDecoder::read_row`] or
+/// [`QoiDecoder::read_pixels`] in the same byte layout as [`crate::decode`]. Pixels are decoded straight into the
+/// caller's buffer; only the part of a run that doesn't fit is buffered internally. Reading stops at the end marker, so
+/// bytes after it are never read.
+pub struct QoiDecoder<R: Read> {
+    reader: BufReader<R>,
+    metadata: ImgMetadata,
+    layout: PixelLayout,
+    state: DecoderState,
+    total_pixels: usize,
+    pixels_decoded: usize,
+    offset: usize,
+    finished: bool,
+    pending: Vec<u8>,
+    pending_start: usize,
+    failed: Option<QoiError>,
+}
+
+impl<R: Read> QoiDecoder<R> {
+    /// Reads the header from `reader`, leaving it positioned at the first chunk.
+    pub fn new(reader: R) -> Result<QoiDecoder<R>, QoiError> {
+        QoiDecoder::with_limits(reader, DecodeLimits::default())
+    }
+
+    /// Reads the header from `reader` like [`QoiDecoder::new`], but fails if the image exceeds `limits`.
+    pub fn with_limits(mut reader: R, limits: DecodeLimits) -> Result<QoiDecoder<R>, QoiError> {
+        let metadata = read_metadata_from(&mut reader)?;
+        limits.check(&metadata, &PixelLayout::from(&metadata.channels))?;
+
+        Ok(QoiDecoder {
+            reader: BufReader::new(reader),
+            layout: PixelLayout::from(&metadata.channels),
+            state: DecoderState::new(),
+            total_pixels: metadata.width as usize * metadata.height as usize,
+            pixels_decoded: 0,
+            offset: HEADER_SIZE,
+            finished: false,
+            pending: Vec::new(),
+            pending_start: 0,
+            failed: None,
+            metadata,
+        })
+    }
+
+    pub fn metadata(&self) -> &ImgMetadata {
+        &self.metadata
+    }
+
+    /// Number of bytes in one decoded row.
+    pub fn row_bytes(&self) -> usize {
+        self.metadata.width as usize * self.layout.bytes_per_pixel()
+    }
+
+    /// Decodes the next row into `row`, which must be exactly [`QoiDecoder::row_bytes`] long.
+    ///
+    /// Returns `Ok(false)` once every row has been read.
+    pub fn read_row(&mut self, row: &mut [u8]) -> Result<bool, QoiError> {
+        if row.len() != self.row_bytes() {
+            return Err(QoiError::BufferSizeMismatch { offset: self.offset, expected: self.row_bytes(), actual: row.len() });
+        }
+
+        let read = self.read_pixels(row)?;
+        //rows are always complete since read_pixels only stops early at the end of the image
+        Ok(read == row.len())
+    }
+
+    /// Decodes pixels into `buf` until it is full or the image ends, returning the number of bytes written.
+    ///
+    /// Returns `Ok(0)` once the whole image has been read. After an error the contents of `buf` are unspecified and
+    /// every later call returns the same error, as the decoder can't tell where the next pixel starts.
+    pub fn read_pixels(&mut self, buf: &mut [u8]) -> Result<usize, QoiError> {
+        if let Some(err) = &self.failed {
+            return Err(err.clone());
+        }
+        self.decode_pixels(buf).map_err(|err| {
+            self.failed = Some(err.clone());
+            err
+        })
+    }
+
+    /// Returns the underlying reader.
+    ///
+    /// Bytes that were buffered but not yet decoded are lost.
+    pub fn into_inner(self) -> R {
+        self.reader.into_inner()
+    }
+
+    fn decode_pixels(&mut self, buf: &mut [u8]) -> Result<usize, QoiError> {
+        let mut written = (self.pending.len() - self.pending_start).min(buf.len());
+        buf[..written].copy_from_slice(&self.pending[self.pending_start..self.pending_start + written]);
+        self.pending_start += written;
+        if self.pending_start < self.pending.len() {
+            return Ok(written);
+        }
+        self.pending.clear();
+        self.pending_start = 0;
+
+        let format = PixelFormat::from(self.layout);
+        while written < buf.len() && self.pixels_decoded < self.total_pixels {
+            let tag_offset = self.offset;
+            let mut chunk = [0; 5];
+            self.read_exact(&mut chunk[..1])?;
+
+            let tag = chunk[0];
+            let operation = parse_operation(&tag);
+            let payload_len = operation_payload_len(&operation);
+            self.read_exact(&mut chunk[1..1 + payload_len]).map_err(|err| match err {
+                QoiError::Truncated { .. } => QoiError::Truncated { offset: tag_offset },
+                err => err,
+            })?;
+
+            if operation == Operation::QoiOpRun && self.pixels_decoded + run_length(&tag) > self.total_pixels {
+                return Err(QoiError::PixelCountMismatch { offset: tag_offset, expected: self.total_pixels, actual: self.pixels_decoded + run_length(&tag) });
+            }
+
+            let mut out = SliceOutput::new(&mut buf[written..]);
+            let pixels = self.state.write_chunk(&mut out, &tag, &mut chunk[1..].iter(), &format);
+            self.pixels_decoded += pixels;
+
+            if out.overflowed() {
+                //every pixel of the chunk is the new previous pixel, so the part that didn't fit can be written again
+                for _ in 0..pixels {
+                    format.write_pixel(&mut self.pending, &self.state.prev_pixel);
+                }
+                self.pending_start = buf.len() - written;
+                written = buf.len();
+            } else {
+                written += out.len();
+            }
+        }
+
+        if self.pixels_decoded == self.total_pixels && !self.finished {
+            self.read_end_marker()?;
+        }
+
+        Ok(written)
+    }
+
+    fn read_end_marker(&mut self) -> Result<(), QoiError> {
+        let end_offset = self.offset;
+        let mut ending = [0; END_MARKER.len()];
+        self.read_exact(&mut ending).map_err(|_| QoiError::MissingEndMarker { offset: end_offset })?;
+        if ending != END_MARKER {
+            return Err(QoiError::MissingEndMarker { offset: end_offset });
+        }
+        self.finished = true;
+        Ok(())
+    }
+
+    fn read_exact(&mut self, buf: &mut [u8]) -> Result<(), QoiError> {
+        read_exact(&mut self.reader, buf, &mut self.offset)
+    }
+}
+
+/// Reads and parses the header from `reader`, consuming exactly the 14 header bytes.
+pub fn read_metadata_from<R: Read>(reader: &mut R) -> Result<ImgMetadata, QoiError> {
+    let mut header = [0; HEADER_SIZE];
+    read_exact(reader, &mut header, &mut 0)?;
+    parse_metadata(&mut header.iter())
+}
+
+/// Fills `buf` from `reader`, advancing `offset` by every byte read so errors point at the right place.
+fn read_exact<R: Read>(reader: &mut R, buf: &mut [u8], offset: &mut usize) -> Result<(), QoiError> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match reader.read(&mut buf[filled..]) {
+            Ok(0) => return Err(QoiError::Truncated { offset: *offset }),
+            Ok(read) => {
+                filled += read;
+                *offset += read;
+            }
+            Err(err) if err.kind() == ErrorKind::Interrupted => {}
+            Err(err) => return Err(QoiError::Io { offset: *offset, kind: err.kind() }),
+        }
+    }
+    Ok(())
+}
+
+
+#[cfg(test)]
+#[allow(clippy::useless_vec)]
+mod tests {
+    use crate::test_util::{header_bytes, metadata};
+    use crate::{Channels, Colorspace, Limit, QOI_OP_RGB};
+
+    use super::*;
+
+    fn stream_test_pixels() -> Vec<u8> {
+        let mut pixels = Vec::new();
+        for y in 0..6u8 {
+            for x in 0..5u8 {
+                //the all black rows make a run that crosses row boundaries
+                match y {
+                    1..=3 => pixels.extend(vec![0, 0, 0]),
+                    _ => pixels.extend(vec![x * 40, y * 3, x + y]),
+                }
+            }
+        }
+        pixels
+    }
+
+    #[test]
+    fn stream_matches_encode() {
+        let metadata = metadata(5, 6, Channels::RGB);
+        let pixels = stream_test_pixels();
+
+        let mut encoder = QoiEncoder::new(Vec::new(), &metadata).unwrap();
+        assert_eq!(encoder.row_bytes(), 15);
+        for row in pixels.chunks(15) {
+            encoder.write_row(row).unwrap();
+        }
+        let streamed = encoder.finish().unwrap();
+
+        assert_eq!(streamed, crate::encode(&pixels, &metadata));
+    }
+
+    #[test]
+    fn stream_wrong_row_length() {
+        let mut encoder = QoiEncoder::new(Vec::new(), &metadata(5, 6, Channels::RGBA)).unwrap();
+        assert_eq!(encoder.write_row(&[0; 15]), Err(EncodeError::BufferSizeMismatch { expected: 20, actual: 15 }));
+    }
+
+    #[test]
+    fn stream_read_wrong_row_length() {
+        let (metadata, pixels) = stream_test_image();
+        let qoi = crate::encode(&pixels, &metadata);
+
+        let mut decoder = QoiDecoder::new(qoi.as_slice()).unwrap();
+        assert_eq!(decoder.read_row(&mut [0; 21]), Err(QoiError::BufferSizeMismatch { offset: 14, expected: 28, actual: 21 }));
+        assert_eq!(decoder.read_row(&mut [0; 29]), Err(QoiError::BufferSizeMismatch { offset: 14, expected: 28, actual: 29 }));
+
+        //nothing was decoded, so the first row is still next
+        let mut row = [0; 28];
+        assert!(decoder.read_row(&mut row).unwrap());
+        assert_eq!(row, pixels[..28]);
+    }
+
+    #[test]
+    fn stream_too_few_rows() {
+        let mut encoder = QoiEncoder::new(Vec::new(), &metadata(5, 6, Channels::RGB)).unwrap();
+        encoder.write_row(&[0; 15]).unwrap();
+        assert_eq!(encoder.finish().err(), Some(EncodeError::PixelCountMismatch { expected: 30, actual: 5 }));
+    }
+
+    #[test]
+    fn stream_too_many_rows() {
+        let mut encoder = QoiEncoder::new(Vec::new(), &metadata(5, 1, Channels::RGB)).unwrap();
+        encoder.write_row(&[0; 15]).unwrap();
+        assert_eq!(encoder.write_row(&[0; 15]), Err(EncodeError::PixelCountMismatch { expected: 5, actual: 10 }));
+    }
+
+    #[test]
+    fn stream_invalid_dimensions() {
+        assert_eq!(QoiEncoder::new(Vec::new(), &metadata(0, 6, Channels::RGB)).err(), Some(EncodeError::ZeroDimensions { width: 0, height: 6 }));
+    }
+
+    fn stream_test_image() -> (ImgMetadata, Vec<u8>) {
+        let metadata = ImgMetadata {
+            width: 7,
+            height: 5,
+            channels: Channels::RGBA,
+            colorspace: Colorspace::SrgbLinearAlpha,
+        };
+        let mut pixels = Vec::new();
+        for y in 0..5u8 {
+            for x in 0..7u8 {
+                //long runs in the first rows cross row boundaries
+                match y {
+                    0 | 1 => pixels.extend(vec![9, 9, 9, 255]),
+                    _ => pixels.extend(vec![x * 30, y * 50, x + y, 255 - x]),
+                }
+            }
+        }
+        (metadata, pixels)
+    }
+
+    #[test]
+    fn stream_read_row() {
+        let (metadata, pixels) = stream_test_image();
+        let qoi = crate::encode(&pixels, &metadata);
+
+        let mut decoder = QoiDecoder::new(qoi.as_slice()).unwrap();
+        assert_eq!(decoder.metadata(), &metadata);
+        assert_eq!(decoder.row_bytes(), 28);
+
+        let mut row = vec![0; decoder.row_bytes()];
+        for expected_row in pixels.chunks(28) {
+            assert!(decoder.read_row(&mut row).unwrap());
+            assert_eq!(row, expected_row);
+        }
+        assert!(!decoder.read_row(&mut row).unwrap());
+    }
+
+    #[test]
+    fn stream_read_pixels() {
+        let (metadata, pixels) = stream_test_image();
+        let mut qoi = crate::encode(&pixels, &metadata);
+        //bytes after the end marker are left unread
+        qoi.extend(vec![1, 2, 3]);
+
+        let mut decoder = QoiDecoder::new(qoi.as_slice()).unwrap();
+        let mut decoded: Vec<u8> = Vec::new();
+        let mut buf = [0; 5];
+        loop {
+            let read = decoder.read_pixels(&mut buf).unwrap();
+            if read == 0 {
+                break;
+            }
+            decoded.extend(&buf[..read]);
+        }
+        assert_eq!(decoded, pixels);
+
+        let mut rest = Vec::new();
+        decoder.into_inner().read_to_end(&mut rest).unwrap();
+        assert!(rest.len() <= 3);
+    }
+
+    #[test]
+    fn stream_buffers_only_overflowing_runs() {
+        let (metadata, pixels) = stream_test_image();
+        let qoi = crate::encode(&pixels, &metadata);
+
+        let mut decoder = QoiDecoder::new(qoi.as_slice()).unwrap();
+        let mut decoded = vec![0; pixels.len()];
+        assert_eq!(decoder.read_pixels(&mut decoded).unwrap(), pixels.len());
+        assert_eq!(decoded, pixels);
+        assert_eq!(decoder.pending.capacity(), 0);
+
+        for buf_len in [1, 3, 4, 9, 30] {
+            let mut decoder = QoiDecoder::new(qoi.as_slice()).unwrap();
+            let mut decoded: Vec<u8> = Vec::new();
+            let mut buf = vec![0; buf_len];
+            loop {
+                let read = decoder.read_pixels(&mut buf).unwrap();
+                if read == 0 {
+                    break;
+                }
+                assert!(decoder.pending.len() <= 62 * 4);
+                decoded.extend(&buf[..read]);
+            }
+            assert_eq!(decoded, pixels, "{buf_len} byte reads");
+        }
+    }
+
+    #[test]
+    fn stream_limits() {
+        let (metadata, pixels) = stream_test_image();
+        let qoi = crate::encode(&pixels, &metadata);
+
+        let limits = DecodeLimits { max_width: 6, ..DecodeLimits::default() };
+        assert_eq!(QoiDecoder::with_limits(qoi.as_slice(), limits).err(), Some(QoiError::LimitExceeded { offset: 4, width: 7, height: 5, limit: Limit::MaxWidth(6) }));
+        assert!(QoiDecoder::with_limits(qoi.as_slice(), DecodeLimits { max_pixels: 35, ..limits }).is_err());
+        assert!(QoiDecoder::with_limits(qoi.as_slice(), DecodeLimits { max_width: 7, max_pixels: 35, ..limits }).is_ok());
+    }
+
+    #[test]
+    fn stream_truncated() {
+        let (metadata, pixels) = stream_test_image();
+        let qoi = crate::encode(&pixels, &metadata);
+
+        assert_eq!(QoiDecoder::new(&qoi[..9]).err(), Some(QoiError::Truncated { offset: 9 }));
+
+        let mut decoder = QoiDecoder::new(&qoi[..qoi.len() - 12]).unwrap();
+        let mut decoded = vec![0; pixels.len()];
+        assert!(decoder.read_pixels(&mut decoded).is_err());
+    }
+
+    #[test]
+    fn stream_stays_failed() {
+        let (metadata, pixels) = stream_test_image();
+        let qoi = crate::encode(&pixels, &metadata);
+
+        //the error comes part way through a buffer, after some of its pixels were written
+        let mut decoder = QoiDecoder::new(&qoi[..qoi.len() - 12]).unwrap();
+        let mut buf = [0; 5];
+        let err = loop {
+            if let Err(err) = decoder.read_pixels(&mut buf) {
+                break err;
+            }
+        };
+        assert!(matches!(err, QoiError::Truncated { .. }));
+        assert_eq!(decoder.read_pixels(&mut buf), Err(err.clone()));
+        assert_eq!(decoder.read_row(&mut [0; 28]), Err(err));
+    }
+
+    #[test]
+    fn stream_missing_end_marker() {
+        let mut bytes = header_bytes(1, 1);
+        bytes.extend(vec![QOI_OP_RGB, 17, 18, 200]);
+        bytes.extend(vec![0, 0, 0, 0, 0, 0, 0, 2]);
+
+        let mut decoder = QoiDecoder::new(bytes.as_slice()).unwrap();
+        let mut row = [0; 3];
+        assert_eq!(decoder.read_row(&mut row), Err(QoiError::MissingEndMarker { offset: 18 }));
+    }
+
+    #[test]
+    fn read_metadata_from_consumes_header() {
+        let mut bytes = header_bytes(3, 4);
+        bytes.extend(vec![1, 2, 3]);
+
+        let mut reader = bytes.as_slice();
+        let metadata = read_metadata_from(&mut reader).unwrap();
+        assert_eq!((metadata.width, metadata.height), (3, 4));
+        assert_eq!(reader, &[1, 2, 3]);
+
+        assert_eq!(read_metadata_from(&mut &bytes[..5]), Err(QoiError::Truncated { offset: 5 }));
+    }
+}
@@ -0,0 +1,30 @@
+//! Fixtures shared by the unit tests.
+
+use alloc::vec::Vec;
+
+use crate::encoder::add_header;
+use crate::{Channels, Colorspace, ImgMetadata};
+
+pub(crate) fn metadata(width: u32, height: u32, channels: Channels) -> ImgMetadata {
+    ImgMetadata {
+        width,
+        height,
+        channels,
+        colorspace: Colorspace::SrgbLinearAlpha,
+    }
+}
+
+/// The header of an RGB image, for building files chunk by chunk.
+pub(crate) fn header_bytes(width: u32, height: u32) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    add_header(&mut bytes, &metadata(width, height, Channels::RGB));
+    bytes
+}
+
+/// RGBA rows of long runs, repeated colors and varied pixels, so rows start inside runs as well as at other chunks.
+pub(crate) fn pixels(width: u32, height: u32) -> Vec<u8> {
+    (0..width * height).flat_map(|i| {
+        let value = if i % 50 < 30 {0} else {(i * 13 % 256) as u8};
+        [value, value / 2, 255 - value, if i % 9 == 0 {128} else {255}]
+    }).collect()
+}
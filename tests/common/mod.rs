@@ -0,0 +1,158 @@
+#![allow(dead_code)]
+
+use jaqoi::{Channels, Colorspace, ImgMetadata};
+
+/// Small deterministic xorshift generator so failures can be reproduced from the seed.
+pub struct Rng(u64);
+
+impl Rng {
+    pub fn new(seed: u64) -> Rng {
+        Rng(seed.wrapping_mul(0x9E37_79B9_7F4A_7C15) | 1)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    pub fn next_u8(&mut self) -> u8 {
+        (self.next_u64() >> 32) as u8
+    }
+
+    /// Returns a value in `0..bound`.
+    pub fn below(&mut self, bound: u64) -> u64 {
+        self.next_u64() % bound
+    }
+}
+
+pub fn metadata(width: u32, height: u32, channels: Channels) -> ImgMetadata {
+    ImgMetadata {
+        width,
+        height,
+        channels,
+        colorspace: Colorspace::SrgbLinearAlpha,
+    }
+}
+
+pub fn channels_per_pixel(channels: &Channels) -> usize {
+    match channels {
+        Channels::RGB => 3,
+        Channels::RGBA => 4,
+    }
+}
+
+/// Uniformly random pixels.
+pub fn noise(rng: &mut Rng, width: u32, height: u32, channels: &Channels) -> Vec<u8> {
+    let len = width as usize * height as usize * channels_per_pixel(channels);
+    (0..len).map(|_| rng.next_u8()).collect()
+}
+
+/// Smooth gradients with small random jitter, exercising QOI_OP_DIFF and QOI_OP_LUMA.
+pub fn gradient(rng: &mut Rng, width: u32, height: u32, channels: &Channels) -> Vec<u8> {
+    let mut pixels = Vec::new();
+    for y in 0..height {
+        for x in 0..width {
+            let jitter = rng.below(3) as u32;
+            pixels.extend([(x * 3 + jitter) as u8, (y * 2) as u8, (x + y + jitter) as u8]);
+            if *channels == Channels::RGBA {
+                pixels.push((255 - x % 256) as u8);
+            }
+        }
+    }
+    pixels
+}
+
+/// Rectangular blocks from a small palette, exercising QOI_OP_RUN and QOI_OP_INDEX.
+pub fn blocks(rng: &mut Rng, width: u32, height: u32, channels: &Channels) -> Vec<u8> {
+    let palette: Vec<[u8; 4]> = (0..6).map(|_| [rng.next_u8(), rng.next_u8(), rng.next_u8(), [0, 128, 255][rng.below(3) as usize]]).collect();
+    let block = 1 + rng.below(9) as u32;
+
+    let mut pixels = Vec::new();
+    for y in 0..height {
+        for x in 0..width {
+            let color = palette[((x / block + y / block * 3) % palette.len() as u32) as usize];
+            pixels.extend(&color[..channels_per_pixel(channels)]);
+        }
+    }
+    pixels
+}
+
+/// Mostly opaque black with a few specks, the case that trips up a wrongly seeded color index.
+pub fn black_start(rng: &mut Rng, width: u32, height: u32, channels: &Channels) -> Vec<u8> {
+    let mut pixels = Vec::new();
+    for _ in 0..width * height {
+        let pixel = match rng.below(8) {
+            0 => [rng.next_u8(), rng.next_u8(), rng.next_u8(), 255],
+            1 => [0, 0, 0, 0],
+            _ => [0, 0, 0, 255],
+        };
+        pixels.extend(&pixel[..channels_per_pixel(channels)]);
+    }
+    pixels
+}
+
+/// Runs of one color whose lengths sit on and around the 62 pixel limit of QOI_OP_RUN.
+pub fn runs(rng: &mut Rng, width: u32, height: u32, channels: &Channels) -> Vec<u8> {
+    const LENGTHS: [u64; 9] = [1, 2, 61, 62, 63, 64, 124, 125, 200];
+
+    let total = width as usize * height as usize;
+    let mut pixels = Vec::new();
+    let mut color = [0, 0, 0, 255];
+    let mut previous = color;
+    while pixels.len() < total * channels_per_pixel(channels) {
+        let length = match rng.below(4) {
+            0 => 1 + rng.below(300),
+            _ => LENGTHS[rng.below(LENGTHS.len() as u64) as usize],
+        };
+        for _ in 0..length.min((total - pixels.len() / channels_per_pixel(channels)) as u64) {
+            pixels.extend(&color[..channels_per_pixel(channels)]);
+        }
+        //switch to a fresh color or back to the previous one, which the index has seen
+        let next = match rng.below(2) {
+            0 => [rng.next_u8(), rng.next_u8(), rng.next_u8(), rng.next_u8()],
+            _ => previous,
+        };
+        previous = color;
+        color = next;
+    }
+    pixels
+}
+
+/// Colors that stay put while alpha changes, exercising QOI_OP_RGBA and indexed alpha.
+pub fn alpha_changes(rng: &mut Rng, width: u32, height: u32, channels: &Channels) -> Vec<u8> {
+    let color = [rng.next_u8(), rng.next_u8(), rng.next_u8()];
+    let mut pixels = Vec::new();
+    for _ in 0..width * height {
+        let alpha = match rng.below(4) {
+            0 => rng.next_u8(),
+            1 => 0,
+            _ => 255,
+        };
+        pixels.extend(&[color[0], color[1], color[2], alpha][..channels_per_pixel(channels)]);
+    }
+    pixels
+}
+
+/// Small steps that cross 0 and 255, exercising the wrapping arithmetic of QOI_OP_DIFF and QOI_OP_LUMA.
+pub fn wraparound(rng: &mut Rng, width: u32, height: u32, channels: &Channels) -> Vec<u8> {
+    let mut pixel: [u8; 4] = [254, 1, 0, 255];
+    let mut pixels = Vec::new();
+    for _ in 0..width * height {
+        let green = rng.below(64) as u8 as i8 - 32;
+        let step = match rng.below(2) {
+            0 => [rng.below(4) as i8 - 2, rng.below(4) as i8 - 2, rng.below(4) as i8 - 2],
+            _ => [green + rng.below(16) as i8 - 8, green, green + rng.below(16) as i8 - 8],
+        };
+        for (channel, step) in pixel.iter_mut().zip(step) {
+            *channel = channel.wrapping_add_signed(step);
+        }
+        pixels.extend(&pixel[..channels_per_pixel(channels)]);
+    }
+    pixels
+}
+
+pub type Generator = fn(&mut Rng, u32, u32, &Channels) -> Vec<u8>;
+
+pub const GENERATORS: [Generator; 7] = [noise, gradient, blocks, black_start, runs, alpha_changes, wraparound];
@@ -0,0 +1,135 @@
+mod common;
+
+use std::panic;
+
+use common::{Rng, GENERATORS};
+use jaqoi::{Channels, DecodeLimits, DecodeOptions, Pixel, PixelLayout, QoiDecoder, SeekTable, Strictness};
+
+const INPUTS: u64 = 300_000;
+
+//random headers may claim billions of pixels, which would be filled in lenient mode
+const LIMITS: DecodeLimits = DecodeLimits {
+    max_width: u32::MAX,
+    max_height: u32::MAX,
+    max_pixels: 1024,
+    max_alloc_bytes: usize::MAX,
+};
+
+/// Valid files to mutate, covering every chunk type.
+fn seed_files(rng: &mut Rng) -> Vec<Vec<u8>> {
+    let mut files = Vec::new();
+    for generator in GENERATORS {
+        for channels in [Channels::RGB, Channels::RGBA] {
+            let width = 1 + rng.below(24) as u32;
+            let height = 1 + rng.below(24) as u32;
+            let pixels = generator(rng, width, height, &channels);
+            files.push(jaqoi::encode(&pixels, &common::metadata(width, height, channels)));
+        }
+    }
+    files
+}
+
+/// A header for a small image followed by random chunk bytes, with or without an end marker.
+fn random_file(rng: &mut Rng) -> Vec<u8> {
+    let mut bytes = b"qoif".to_vec();
+    bytes.extend((1 + rng.below(16) as u32).to_be_bytes());
+    bytes.extend((1 + rng.below(16) as u32).to_be_bytes());
+    bytes.extend([3 + rng.below(2) as u8, rng.below(2) as u8]);
+    for _ in 0..rng.below(256) {
+        bytes.push(rng.next_u8());
+    }
+    if rng.below(2) == 0 {
+        bytes.extend([0, 0, 0, 0, 0, 0, 0, 1]);
+    }
+    bytes
+}
+
+/// Applies a few random bit flips, overwrites, insertions, deletions and truncations to `bytes`.
+fn mutate(rng: &mut Rng, mut bytes: Vec<u8>) -> Vec<u8> {
+    for _ in 0..1 + rng.below(4) {
+        if bytes.is_empty() {
+            break;
+        }
+        let at = rng.below(bytes.len() as u64) as usize;
+        match rng.below(5) {
+            0 => bytes[at] ^= 1 << rng.below(8),
+            1 => bytes[at] = rng.next_u8(),
+            2 => bytes.insert(at, rng.next_u8()),
+            3 => {
+                bytes.remove(at);
+            }
+            _ => bytes.truncate(at),
+        }
+    }
+    bytes
+}
+
+/// Runs `bytes` through every fallible decoding entry point. Errors are fine, only panics fail the test.
+///
+/// `other_table` belongs to some other file, so decoding with it exercises tables that don't match.
+fn decode_everything(bytes: &[u8], out: &mut [u8], other_table: &SeekTable) {
+    let strict = DecodeOptions::new().with_limits(LIMITS);
+    let _ = strict.decode(bytes);
+    let _ = strict.clone().with_layout(PixelLayout::LA8).with_premultiplied_alpha(true).decode(bytes);
+
+    let lenient = strict.clone().with_strictness(Strictness::Lenient { fill: Pixel::new(1, 2, 3, 4) });
+    if let Ok((metadata, pixels, _)) = lenient.clone().with_layout(PixelLayout::BGRA).decode_with_problems(bytes) {
+        assert_eq!(pixels.len(), metadata.width as usize * metadata.height as usize * 4);
+    }
+
+    let _ = lenient.with_layout(PixelLayout::ARGB).decode_into(bytes, out);
+    let _ = jaqoi::decode_to_slice(bytes, &mut out[..100]);
+    let _ = jaqoi::read_metadata(bytes);
+
+    //a table can only be built for a valid file, which it must decode the same as the plain decoder
+    if let (Ok(table), Ok((_, pixels))) = (SeekTable::build(bytes, 2), strict.clone().with_layout(PixelLayout::RGBA).decode(bytes)) {
+        assert_eq!(jaqoi::decode_parallel(bytes, &table, PixelLayout::RGBA, &LIMITS, 3).unwrap().1, pixels);
+    }
+    for table in [SeekTable::from_trailer(bytes), Ok(other_table.clone())].into_iter().flatten() {
+        let _ = jaqoi::decode_rows(bytes, &table, 1..3, PixelLayout::RGB, &LIMITS);
+        let _ = jaqoi::decode_parallel(bytes, &table, PixelLayout::RGBA, &LIMITS, 3);
+    }
+
+    if let Ok(mut decoder) = QoiDecoder::with_limits(bytes, LIMITS) {
+        let mut buf = [0; 37];
+        while let Ok(read) = decoder.read_pixels(&mut buf) {
+            if read == 0 {
+                break;
+            }
+        }
+    }
+}
+
+#[test]
+fn seeded_inputs_never_panic() {
+    let mut rng = Rng::new(0x5EED);
+    let seeds = seed_files(&mut rng);
+    let tables: Vec<SeekTable> = seeds.iter().map(|file| SeekTable::build(file, 1 + rng.below(3) as u32).unwrap()).collect();
+    let mut out = vec![0; 1024 * 4];
+
+    for i in 0..INPUTS {
+        let bytes = match rng.below(5) {
+            0 => random_file(&mut rng),
+            //mutations of a file with its seek table appended reach the table as well as the chunks
+            1 => {
+                let index = rng.below(seeds.len() as u64) as usize;
+                let mut file = seeds[index].clone();
+                file.extend_from_slice(&tables[index].to_bytes());
+                mutate(&mut rng, file)
+            }
+            2 => {
+                let file = random_file(&mut rng);
+                mutate(&mut rng, file)
+            }
+            _ => {
+                let file = seeds[rng.below(seeds.len() as u64) as usize].clone();
+                mutate(&mut rng, file)
+            }
+        };
+
+        let table = &tables[rng.below(tables.len() as u64) as usize];
+        if panic::catch_unwind(panic::AssertUnwindSafe(|| decode_everything(&bytes, &mut out, table))).is_err() {
+            panic!("input {} panicked: {:?}", i, bytes);
+        }
+    }
+}
@@ -0,0 +1,114 @@
+use std::io::Cursor;
+
+use jaqoi::{Channels, PixelLayout};
+
+mod common;
+
+use common::{channels_per_pixel, Rng, GENERATORS};
+
+const SIZES: [(u32, u32); 8] = [(1, 1), (1, 17), (17, 1), (2, 2), (8, 8), (31, 7), (64, 3), (100, 100)];
+
+fn decode_with_image(qoi: &[u8]) -> (Channels, Vec<u8>) {
+    let mut reader = image::io::Reader::new(Cursor::new(qoi));
+    reader.set_format(image::ImageFormat::Qoi);
+    let decoded = reader.decode().expect("image should decode jaqoi output");
+    match decoded {
+        image::DynamicImage::ImageRgb8(img) => (Channels::RGB, img.into_raw()),
+        image::DynamicImage::ImageRgba8(img) => (Channels::RGBA, img.into_raw()),
+        other => panic!("Unexpected color type {:?}", other.color()),
+    }
+}
+
+fn encode_with_image(pixels: Vec<u8>, width: u32, height: u32, channels: &Channels) -> Vec<u8> {
+    let img = match channels {
+        Channels::RGB => image::DynamicImage::from(image::RgbImage::from_raw(width, height, pixels).unwrap()),
+        Channels::RGBA => image::DynamicImage::from(image::RgbaImage::from_raw(width, height, pixels).unwrap()),
+    };
+    let mut qoi = Vec::new();
+    img.write_to(&mut Cursor::new(&mut qoi), image::ImageFormat::Qoi).expect("image should encode QOI");
+    qoi
+}
+
+#[test]
+fn jaqoi_output_decodes_with_image() {
+    let mut rng = Rng::new(3);
+    for generator in GENERATORS {
+        for (width, height) in SIZES {
+            for channels in [Channels::RGB, Channels::RGBA] {
+                let channel_count = channels_per_pixel(&channels);
+                let pixels = generator(&mut rng, width, height, &channels);
+                let qoi = jaqoi::encode(&pixels, &common::metadata(width, height, channels));
+
+                let (decoded_channels, decoded) = decode_with_image(&qoi);
+                assert_eq!(channels_per_pixel(&decoded_channels), channel_count);
+                assert_eq!(decoded, pixels, "{width}x{height} {decoded_channels:?}");
+            }
+        }
+    }
+}
+
+#[test]
+fn parallel_output_decodes_with_image() {
+    let mut rng = Rng::new(5);
+    for generator in GENERATORS {
+        for (width, height) in SIZES {
+            for channels in [Channels::RGB, Channels::RGBA] {
+                let channel_count = channels_per_pixel(&channels);
+                let pixels = generator(&mut rng, width, height, &channels);
+                let metadata = common::metadata(width, height, channels);
+
+                for strips in [2, 3, 8, 1000] {
+                    let qoi = jaqoi::try_encode_parallel(&pixels, PixelLayout::from(&metadata.channels), &metadata, strips).unwrap();
+
+                    let (decoded_channels, decoded) = decode_with_image(&qoi);
+                    assert_eq!(channels_per_pixel(&decoded_channels), channel_count);
+                    assert_eq!(decoded, pixels, "{width}x{height} {decoded_channels:?} in {strips} strips");
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn appended_seek_table_decodes_with_image() {
+    let mut rng = Rng::new(9);
+    for generator in GENERATORS {
+        for channels in [Channels::RGB, Channels::RGBA] {
+            let pixels = generator(&mut rng, 31, 7, &channels);
+            let metadata = common::metadata(31, 7, channels);
+            let (mut qoi, table) = jaqoi::try_encode_with_seek_table(&pixels, PixelLayout::from(&metadata.channels), &metadata, 2).unwrap();
+            qoi.extend_from_slice(&table.to_bytes());
+
+            assert_eq!(decode_with_image(&qoi).1, pixels);
+        }
+    }
+}
+
+#[test]
+fn image_output_decodes_with_jaqoi() {
+    let mut rng = Rng::new(7);
+    for generator in GENERATORS {
+        for (width, height) in SIZES {
+            for channels in [Channels::RGB, Channels::RGBA] {
+                let pixels = generator(&mut rng, width, height, &channels);
+                let qoi = encode_with_image(pixels.clone(), width, height, &channels);
+
+                let (metadata, decoded) = jaqoi::try_decode(&qoi).expect("jaqoi should decode image output");
+                assert_eq!(metadata, common::metadata(width, height, channels));
+                assert_eq!(decoded, pixels, "{width}x{height} {:?}", metadata.channels);
+            }
+        }
+    }
+}
+
+#[test]
+fn opaque_black_after_other_colors() {
+    //a black pixel that isn't in the index yet must not be written as QOI_OP_INDEX
+    let pixels = vec![0, 0, 0, 255,
+                      100, 100, 100, 255,
+                      0, 0, 0, 255,
+                      0, 0, 0, 255];
+    let qoi = jaqoi::encode(&pixels, &common::metadata(4, 1, Channels::RGBA));
+
+    assert_eq!(decode_with_image(&qoi).1, pixels);
+}
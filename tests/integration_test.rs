@@ -1,2 +1,60 @@
-use jaqoi;
+use jaqoi::{Channels, Colorspace, ImgMetadata, QoiError};
 
+fn gradient(width: u32, height: u32) -> Vec<u8> {
+    let mut pixels = Vec::new();
+    for y in 0..height {
+        for x in 0..width {
+            pixels.extend([(x * 7) as u8, (y * 13) as u8, (x * y) as u8]);
+        }
+    }
+    pixels
+}
+
+#[test]
+fn try_decode_round_trip() {
+    let metadata = ImgMetadata {
+        width: 16,
+        height: 9,
+        channels: Channels::RGB,
+        colorspace: Colorspace::SrgbLinearAlpha,
+    };
+    let pixels = gradient(16, 9);
+    let qoi = jaqoi::encode(&pixels, &metadata);
+
+    let (decoded_metadata, decoded) = jaqoi::try_decode(&qoi).expect("Decode should be successful");
+    assert_eq!(decoded_metadata, metadata);
+    assert_eq!(decoded, pixels);
+}
+
+#[test]
+fn try_decode_every_truncation_is_an_error() {
+    let metadata = ImgMetadata {
+        width: 16,
+        height: 9,
+        channels: Channels::RGB,
+        colorspace: Colorspace::SrgbLinearAlpha,
+    };
+    let qoi = jaqoi::encode(&gradient(16, 9), &metadata);
+
+    for len in 0..qoi.len() {
+        let err = jaqoi::try_decode(&qoi[..len]).expect_err("Truncated file should not decode");
+        assert!(err.offset() <= len, "{err} reported past the end of {len} bytes");
+    }
+}
+
+#[test]
+fn try_decode_reports_offsets() {
+    let metadata = ImgMetadata {
+        width: 1,
+        height: 1,
+        channels: Channels::RGBA,
+        colorspace: Colorspace::AllLinearAlpha,
+    };
+    let mut qoi = jaqoi::encode(&[1, 2, 3, 4], &metadata);
+
+    qoi.extend([9, 9]);
+    assert_eq!(jaqoi::try_decode(&qoi), Err(QoiError::TrailingBytes { offset: 27 }));
+
+    qoi[0] = b'Q';
+    assert_eq!(jaqoi::try_decode(&qoi), Err(QoiError::InvalidMagic { offset: 0 }));
+}
@@ -0,0 +1,65 @@
+mod common;
+
+use common::{Rng, GENERATORS};
+use jaqoi::{Channels, PixelLayout};
+
+/// Sizes covering single pixels, single rows and columns, and pixel counts around multiples of the 62 pixel run limit.
+const SIZES: [(u32, u32); 20] = [
+    (1, 1), (1, 2), (2, 1), (1, 61), (1, 62), (1, 63), (62, 1), (63, 1), (1, 124), (125, 1),
+    (1, 300), (300, 1), (2, 31), (7, 9), (4, 31), (5, 25), (13, 17), (64, 64), (100, 37), (257, 3),
+];
+
+fn round_trip(pixels: &[u8], width: u32, height: u32, channels: Channels) {
+    let layout = PixelLayout::from(&channels);
+    let metadata = common::metadata(width, height, channels);
+    let qoi = jaqoi::try_encode(pixels, &metadata).expect("generated image should encode");
+    assert!(qoi.len() <= jaqoi::max_encoded_len(&metadata));
+
+    let (decoded_metadata, decoded) = jaqoi::try_decode(&qoi).expect("encoded image should decode");
+    assert_eq!(decoded_metadata, metadata);
+    assert_eq!(decoded, pixels, "{width}x{height} {layout:?}");
+
+    let mut out = vec![0; pixels.len()];
+    assert_eq!(jaqoi::decode_into_with_layout(&qoi, &mut out, layout).map(|(_, len)| len), Ok(pixels.len()));
+    assert_eq!(out, pixels, "{width}x{height} {layout:?} into a slice");
+}
+
+#[test]
+fn every_generator_round_trips() {
+    for seed in 0..4 {
+        let mut rng = Rng::new(seed);
+        for generator in GENERATORS {
+            for (width, height) in SIZES {
+                for channels in [Channels::RGB, Channels::RGBA] {
+                    let pixels = generator(&mut rng, width, height, &channels);
+                    round_trip(&pixels, width, height, channels);
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn random_sizes_round_trip() {
+    let mut rng = Rng::new(20);
+    for _ in 0..200 {
+        let width = 1 + rng.below(90) as u32;
+        let height = 1 + rng.below(90) as u32;
+        let generator = GENERATORS[rng.below(GENERATORS.len() as u64) as usize];
+        for channels in [Channels::RGB, Channels::RGBA] {
+            let pixels = generator(&mut rng, width, height, &channels);
+            round_trip(&pixels, width, height, channels);
+        }
+    }
+}
+
+#[test]
+fn runs_split_at_62_pixels() {
+    //the image starts on the implicit previous pixel, so a flat opaque black image is nothing but runs
+    for (pixel_count, runs) in [(1, 1), (61, 1), (62, 1), (63, 2), (124, 2), (125, 3), (300, 5)] {
+        let pixels = [0, 0, 0, 255].repeat(pixel_count);
+        let qoi = jaqoi::encode(&pixels, &common::metadata(pixel_count as u32, 1, Channels::RGBA));
+        assert_eq!(qoi.len(), 14 + runs + 8, "{pixel_count} pixels");
+        round_trip(&pixels, 1, pixel_count as u32, Channels::RGBA);
+    }
+}